@@ -0,0 +1,237 @@
+use crate::diamond::Diamond;
+use crate::error::TilingError;
+use crate::render::{Colors, cell_pixels};
+use crate::tiling::Tiling;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{EncodingError, ImageFormatHint};
+use image::imageops::replace;
+use image::{Delay, Frame, ImageError, ImageFormat, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Container used by [`Animation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// Animated GIF, quantized to a 256 color palette per frame.
+    Gif,
+    /// Animated PNG, lossless.
+    Apng,
+    /// Uncompressed YUV4MPEG2 video in 4:2:0 with BT.601 colors, to be piped
+    /// into an encoder.
+    Y4m,
+}
+
+impl AnimationFormat {
+    /// GIF for a `.gif` extension, Y4M for `.y4m` and for `-`, which stands
+    /// for standard output, APNG otherwise.
+    pub fn from_path(path: &str) -> AnimationFormat {
+        match std::path::Path::new(path).extension() {
+            _ if path == "-" => AnimationFormat::Y4m,
+            Some(ext) if ext.eq_ignore_ascii_case("gif") => AnimationFormat::Gif,
+            Some(ext) if ext.eq_ignore_ascii_case("y4m") => AnimationFormat::Y4m,
+            _ => AnimationFormat::Apng,
+        }
+    }
+}
+
+/// Timing of an [`Animation`].
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationOptions {
+    /// Time each frame is shown, in milliseconds. Y4M turns it into the
+    /// frame rate.
+    pub delay: u16,
+    /// Number of times the animation plays, zero for forever. Ignored by Y4M.
+    pub loops: u16,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> AnimationOptions {
+        AnimationOptions {
+            delay: 100,
+            loops: 0,
+        }
+    }
+}
+
+enum Encoder {
+    Gif(Box<GifEncoder<BufWriter<File>>>),
+    Apng(png::Writer<BufWriter<File>>),
+    Y4m(Box<dyn Write>),
+}
+
+/// Animated image or video of a growing diamond written frame by frame.
+///
+/// Every frame is drawn on a canvas sized for the final diamond, with the
+/// current diamond centered on it, so frames line up while it grows.
+pub struct Animation {
+    path: String,
+    encoder: Encoder,
+    size: usize,
+    ts: usize,
+    delay: u16,
+}
+
+impl Animation {
+    /// Starts an animation of `frames` frames at `path` for diamonds up to
+    /// `size` cells across, drawn with `ts` pixels per cell. A Y4M stream
+    /// goes to standard output when `path` is `-`.
+    pub fn create(
+        path: &str,
+        format: AnimationFormat,
+        size: usize,
+        ts: usize,
+        frames: usize,
+        options: AnimationOptions,
+    ) -> Result<Animation, TilingError> {
+        let side = (size * cell_pixels(ts)) as u32;
+        let encoder = match format {
+            AnimationFormat::Y4m => {
+                let mut out: Box<dyn Write> = if path == "-" {
+                    Box::new(BufWriter::new(std::io::stdout()))
+                } else {
+                    Box::new(create_file(path)?)
+                };
+                writeln!(
+                    out,
+                    "YUV4MPEG2 W{side} H{side} F1000:{} Ip A1:1 C420jpeg",
+                    options.delay.max(1)
+                )
+                .map_err(|err| TilingError::io(path, err))?;
+                Encoder::Y4m(out)
+            }
+            AnimationFormat::Gif => {
+                let mut encoder = GifEncoder::new_with_speed(create_file(path)?, 10);
+                // Without a repeat extension a GIF plays once.
+                let repeat = match options.loops {
+                    0 => Some(Repeat::Infinite),
+                    1 => None,
+                    n => Some(Repeat::Finite(n - 1)),
+                };
+                if let Some(repeat) = repeat {
+                    encoder
+                        .set_repeat(repeat)
+                        .map_err(|source| image_error(path, source))?;
+                }
+                Encoder::Gif(Box::new(encoder))
+            }
+            AnimationFormat::Apng => {
+                let mut encoder = png::Encoder::new(create_file(path)?, side, side);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .set_animated(frames as u32, options.loops as u32)
+                    .and_then(|()| encoder.set_frame_delay(options.delay, 1000))
+                    .map_err(|err| png_error(path, err))?;
+                Encoder::Apng(encoder.write_header().map_err(|err| png_error(path, err))?)
+            }
+        };
+        Ok(Animation {
+            path: path.to_string(),
+            encoder,
+            size,
+            ts,
+            delay: options.delay,
+        })
+    }
+
+    /// Appends the current state of `x` as the next frame.
+    pub fn add_frame(&mut self, x: &Diamond, colors: &Colors) -> Result<(), TilingError> {
+        let px = cell_pixels(self.ts);
+        let side = (self.size * px) as u32;
+        let mut canvas = RgbaImage::from_pixel(side, side, Rgba([128, 128, 128, 255]));
+        let offset = (self.size.saturating_sub(x.size()) / 2 * px) as i64;
+        replace(&mut canvas, &x.render(self.ts, colors), offset, offset);
+        match &mut self.encoder {
+            Encoder::Gif(encoder) => encoder
+                .encode_frame(Frame::from_parts(
+                    canvas,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(self.delay as u32, 1),
+                ))
+                .map_err(|source| image_error(&self.path, source)),
+            Encoder::Apng(writer) => writer
+                .write_image_data(canvas.as_raw())
+                .map_err(|err| png_error(&self.path, err)),
+            Encoder::Y4m(out) => out
+                .write_all(b"FRAME\n")
+                .and_then(|()| out.write_all(&yuv420(&canvas)))
+                .map_err(|err| TilingError::io(&self.path, err)),
+        }
+    }
+
+    /// Writes the trailer. The APNG must have received every announced frame.
+    pub fn finish(self) -> Result<(), TilingError> {
+        match self.encoder {
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Apng(writer) => writer.finish().map_err(|err| png_error(&self.path, err))?,
+            Encoder::Y4m(mut out) => out
+                .flush()
+                .map_err(|err| TilingError::io(&self.path, err))?,
+        }
+        Ok(())
+    }
+}
+
+/// Converts an image with even sides to planar BT.601 studio range Y'CbCr,
+/// averaging chroma over 2x2 blocks.
+fn yuv420(im: &RgbaImage) -> Vec<u8> {
+    let (w, h) = im.dimensions();
+    let rgb = |x: u32, y: u32| {
+        let [r, g, b, _] = im.get_pixel(x, y).0;
+        [r as f64, g as f64, b as f64]
+    };
+    let luma = (0..h).flat_map(|y| {
+        (0..w).map(move |x| {
+            let [r, g, b] = rgb(x, y);
+            (16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8
+        })
+    });
+    let blocks: Vec<[f64; 3]> = (0..h / 2)
+        .flat_map(|y| {
+            (0..w / 2).map(move |x| {
+                let mut sum = [0.0; 3];
+                [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .for_each(|(dx, dy)| {
+                        let c = rgb(2 * x + dx, 2 * y + dy);
+                        (0..3).for_each(|k| sum[k] += c[k] / 4.0);
+                    });
+                sum
+            })
+        })
+        .collect();
+    let cb = blocks
+        .iter()
+        .map(|[r, g, b]| (128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8);
+    let cr = blocks
+        .iter()
+        .map(|[r, g, b]| (128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8);
+    luma.chain(cb).chain(cr).collect()
+}
+
+fn create_file(path: &str) -> Result<BufWriter<File>, TilingError> {
+    Ok(BufWriter::new(
+        File::create(path).map_err(|err| TilingError::io(path, err))?,
+    ))
+}
+
+fn image_error(path: &str, source: ImageError) -> TilingError {
+    TilingError::Image {
+        path: Some(path.to_string()),
+        source,
+    }
+}
+
+fn png_error(path: &str, err: png::EncodingError) -> TilingError {
+    match err {
+        png::EncodingError::IoError(err) => TilingError::io(path, err),
+        err => image_error(
+            path,
+            ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Png),
+                err,
+            )),
+        ),
+    }
+}
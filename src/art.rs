@@ -0,0 +1,131 @@
+use crate::diamond::{Coords, Direction};
+use crate::region::Region;
+use crate::render::Colors;
+use image::imageops::{FilterType, resize};
+use image::{RgbImage, Rgba};
+use rand::Rng;
+
+/// Cooling schedule of [`Region::anneal`].
+#[derive(Clone, Copy, Debug)]
+pub struct Annealing {
+    /// Number of iterations, each proposing as many flips as the region has
+    /// 2x2 blocks.
+    pub iterations: usize,
+    /// Temperature of the first iteration.
+    pub start_temperature: f64,
+    /// Temperature of the last iteration, reached geometrically.
+    pub end_temperature: f64,
+}
+
+impl Default for Annealing {
+    fn default() -> Annealing {
+        Annealing {
+            iterations: 100,
+            start_temperature: 1.0,
+            end_temperature: 0.01,
+        }
+    }
+}
+
+impl Region {
+    /// Searches for a tiling whose colors approximate `target` by simulated
+    /// annealing over flips, and returns the error before the first and
+    /// after every iteration.
+    ///
+    /// The target is resized to one pixel per cell. The error is the mean
+    /// over the cells of the region of the squared distance between the
+    /// color of the covering domino in `colors` and the target pixel, with
+    /// channels scaled to `[0, 1]`. A flip that changes the error by `d` is
+    /// accepted with probability `exp(-d / t)` at temperature `t`.
+    pub fn anneal(&mut self, target: &RgbImage, colors: &Colors, schedule: &Annealing) -> Vec<f64> {
+        let (rows, cols) = (self.rows(), self.cols());
+        let target = resize(target, cols as u32, rows as u32, FilterType::Triangle);
+        let fill = |dir: Direction| match dir {
+            Direction::T => colors.top,
+            Direction::B => colors.bottom,
+            Direction::L => colors.left,
+            Direction::R => colors.right,
+        };
+        let cost = |(i, j): Coords, color: Rgba<u8>| {
+            let pixel = target.get_pixel(j as u32, i as u32).0;
+            (0..3)
+                .map(|k| (color.0[k] as f64 - pixel[k] as f64) / 255.0)
+                .map(|d| d * d)
+                .sum::<f64>()
+        };
+        // Cost of the four cells of a block covered by two parallel
+        // dominoes.
+        let block_cost = |data: &[Option<Direction>], (i, j): Coords| {
+            let at = |c: Coords| data[c.0 * cols + c.1];
+            match (at((i, j)), at((i + 1, j)), at((i, j + 1))) {
+                (Some(a @ (Direction::T | Direction::B)), Some(b), _) => {
+                    cost((i, j), fill(a))
+                        + cost((i, j + 1), fill(a))
+                        + cost((i + 1, j), fill(b))
+                        + cost((i + 1, j + 1), fill(b))
+                }
+                (Some(a), _, Some(b)) => {
+                    cost((i, j), fill(a))
+                        + cost((i + 1, j), fill(a))
+                        + cost((i, j + 1), fill(b))
+                        + cost((i + 1, j + 1), fill(b))
+                }
+                _ => 0.0,
+            }
+        };
+        let cells = (2 * self.tiles().count()).max(1) as f64;
+        let mut error: f64 = self
+            .tiles()
+            .map(|tile| {
+                let (i, j) = tile.pos;
+                let other = match tile.dir {
+                    Direction::T | Direction::B => (i, j + 1),
+                    Direction::L | Direction::R => (i + 1, j),
+                };
+                cost((i, j), fill(tile.dir)) + cost(other, fill(tile.dir))
+            })
+            .sum();
+        let mut errors = vec![error / cells];
+        let faces = self.faces();
+        if faces.is_empty() {
+            return errors;
+        }
+        let ratio = (schedule.end_temperature / schedule.start_temperature)
+            .powf(1.0 / schedule.iterations.saturating_sub(1).max(1) as f64);
+        let mut data = std::mem::take(&mut self.data);
+        (0..schedule.iterations).for_each(|iteration| {
+            let temperature = schedule.start_temperature * ratio.powi(iteration as i32);
+            (0..faces.len()).for_each(|_| {
+                let face = faces[self.rng.random_range(0..faces.len())];
+                let (i, j) = face;
+                let horizontal = matches!(
+                    (data[i * cols + j], data[(i + 1) * cols + j]),
+                    (
+                        Some(Direction::T | Direction::B),
+                        Some(Direction::T | Direction::B)
+                    )
+                );
+                // Raising turns a black block horizontal, so this proposes
+                // the other orientation.
+                let up = horizontal != Region::black(face);
+                let before = block_cost(&data, face);
+                let saved = [(i, j), (i + 1, j), (i, j + 1)].map(|(a, b)| data[a * cols + b]);
+                if !Region::flip(cols, &mut data, face, up) {
+                    return;
+                }
+                let delta = block_cost(&data, face) - before;
+                if delta <= 0.0 || self.rng.random::<f64>() < (-delta / temperature).exp() {
+                    error += delta;
+                } else {
+                    [(i, j), (i + 1, j), (i, j + 1)]
+                        .iter()
+                        .zip(saved)
+                        .for_each(|((a, b), d)| data[a * cols + b] = d);
+                }
+            });
+            errors.push(error / cells);
+        });
+        self.data = data;
+        errors
+    }
+}
@@ -0,0 +1,228 @@
+use crate::diamond::{Diamond, Direction};
+use crate::error::TilingError;
+use crate::expression::Expression;
+use crate::weights::Weights;
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// First bytes of every binary state.
+pub(crate) const MAGIC: &[u8; 4] = b"TLNG";
+/// Version of the layout written by [`Diamond::to_binary`].
+const VERSION: u16 = 1;
+/// Bytes before the parameters: magic, version, reserved, order and
+/// parameter length.
+const HEADER: usize = 4 + 2 + 2 + 8 + 4;
+
+/// How a diamond's state is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateFormat {
+    /// Human-readable JSON, see [`Tiling::to_json`](crate::Tiling::to_json).
+    Json,
+    /// The compact format of [`Diamond::to_binary`].
+    Binary,
+}
+
+impl StateFormat {
+    /// Picks the binary format for paths ending in `.bin` and JSON
+    /// otherwise.
+    pub fn from_path(path: &str) -> StateFormat {
+        match std::path::Path::new(path).extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("bin") => StateFormat::Binary,
+            _ => StateFormat::Json,
+        }
+    }
+}
+
+impl FromStr for StateFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<StateFormat, String> {
+        match input.to_ascii_lowercase().as_str() {
+            "json" => Ok(StateFormat::Json),
+            "bin" | "binary" => Ok(StateFormat::Binary),
+            _ => Err(format!("{input}: expected json or binary")),
+        }
+    }
+}
+
+/// Everything but the dominoes, stored as JSON inside the binary state.
+#[derive(Serialize, Deserialize)]
+struct Parameters {
+    p: f64,
+    steps: usize,
+    rng: Pcg64,
+    weights: Option<Weights>,
+    expression: Option<Expression>,
+}
+
+fn invalid(message: &str) -> TilingError {
+    TilingError::Binary(message.to_string())
+}
+
+impl Diamond {
+    /// Encodes a fully tiled diamond in a compact binary format.
+    ///
+    /// The layout is little-endian: the magic bytes `TLNG`, a `u16` version,
+    /// a reserved `u16`, the order as `u64`, the length of the parameters as
+    /// `u32` followed by the parameters as JSON, then two bits per domino
+    /// holding its direction minus one, packed from the lowest bit, and
+    /// finally a CRC-32 of everything before it. Dominoes are listed in the
+    /// order their top-left cells appear row by row, so their positions
+    /// follow from the directions alone.
+    pub fn to_binary(&self) -> Result<Vec<u8>, TilingError> {
+        let parameters = serde_json::to_vec(&Parameters {
+            p: self.p,
+            steps: self.steps,
+            rng: self.rng.clone(),
+            weights: self.weights.clone(),
+            expression: self.expression.clone(),
+        })
+        .map_err(TilingError::State)?;
+        let order = self.order();
+        let dominoes = order * (order + 1);
+        let mut bytes = Vec::with_capacity(HEADER + parameters.len() + dominoes.div_ceil(4) + 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&(order as u64).to_le_bytes());
+        bytes.extend_from_slice(&(parameters.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&parameters);
+        let start = bytes.len();
+        bytes.resize(start + dominoes.div_ceil(4), 0);
+        let mut k = 0;
+        for i in 0..self.size {
+            for j in self.span(i) {
+                match self.at(i, j) {
+                    Some(dir) => {
+                        bytes[start + k / 4] |= (dir as u8 - 1) << (2 * (k % 4));
+                        k += 1;
+                    }
+                    None if self.owner(i, j).is_none() => {
+                        return Err(invalid(&format!(
+                            "cell ({i}, {j}) is not covered, only complete diamonds can be encoded"
+                        )));
+                    }
+                    None => {}
+                }
+            }
+        }
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        Ok(bytes)
+    }
+    /// Restores a diamond encoded with [`Diamond::to_binary`].
+    pub fn from_binary(bytes: &[u8]) -> Result<Diamond, TilingError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid("missing magic bytes"));
+        }
+        if bytes.len() < HEADER + 4 {
+            return Err(invalid("truncated header"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32fast::hash(body).to_le_bytes() != checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let mut buf = [0; 8];
+            buf[..range.len()].copy_from_slice(&body[range]);
+            u64::from_le_bytes(buf)
+        };
+        let version = field(4..6);
+        if version != VERSION as u64 {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+        let order = usize::try_from(field(8..16)).map_err(|_| invalid("order too large"))?;
+        let length = field(16..20) as usize;
+        let parameters = body
+            .get(HEADER..HEADER + length)
+            .ok_or_else(|| invalid("truncated parameters"))?;
+        let parameters: Parameters =
+            serde_json::from_slice(parameters).map_err(TilingError::State)?;
+        let packed = &body[HEADER + length..];
+        let dominoes = order
+            .checked_add(1)
+            .and_then(|n| order.checked_mul(n))
+            .filter(|n| n.div_ceil(4) == packed.len())
+            .ok_or_else(|| invalid("domino count does not match the order"))?;
+        let mut k = 0;
+        let mut x = Diamond::from_dominoes(order, parameters.p, |_| {
+            if k == dominoes {
+                return Err(invalid("too few dominoes"));
+            }
+            let dir = match (packed[k / 4] >> (2 * (k % 4))) & 3 {
+                0 => Direction::T,
+                1 => Direction::B,
+                2 => Direction::L,
+                _ => Direction::R,
+            };
+            k += 1;
+            Ok(Some(dir))
+        })?;
+        x.rng = parameters.rng;
+        x.weights = parameters.weights;
+        x.expression = parameters.expression;
+        x.steps = parameters.steps;
+        x.validate()?;
+        Ok(x)
+    }
+    /// Writes the diamond to `path` in the format of
+    /// [`Diamond::to_binary`].
+    pub fn save_binary(&self, path: &str) -> Result<(), TilingError> {
+        std::fs::write(path, self.to_binary()?).map_err(|err| TilingError::io(path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grown() -> Diamond {
+        let weights: Weights = "1:2:1:0.5;0.5:1:2:1".parse().expect("weights");
+        let mut x = Diamond::new(0.3, 10, 11, Some(weights.with_order(5)));
+        x.generate(5, None, |_| {}).expect("shuffling");
+        x
+    }
+
+    /// Rewrites the order of an encoded state and its checksum.
+    fn with_order(bytes: &[u8], order: u64) -> Vec<u8> {
+        let mut bytes = bytes[..bytes.len() - 4].to_vec();
+        bytes[8..16].copy_from_slice(&order.to_le_bytes());
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let x = grown();
+        let bytes = x.to_binary().expect("encoding");
+        let y = Diamond::from_binary(&bytes).expect("decoding");
+        assert_eq!(y.to_binary().expect("encoding"), bytes);
+        assert_eq!(
+            crate::schema::encode(&y).expect("json"),
+            crate::schema::encode(&x).expect("json")
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_states() {
+        let bytes = grown().to_binary().expect("encoding");
+        let mut flipped = bytes.clone();
+        flipped[HEADER] ^= 1;
+        assert!(matches!(
+            Diamond::from_binary(&flipped),
+            Err(TilingError::Binary(_))
+        ));
+        assert!(matches!(
+            Diamond::from_binary(&bytes[..HEADER]),
+            Err(TilingError::Binary(_))
+        ));
+        for order in [4, u64::MAX] {
+            assert!(matches!(
+                Diamond::from_binary(&with_order(&bytes, order)),
+                Err(TilingError::Binary(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,761 @@
+use crate::binary::MAGIC;
+use crate::error::TilingError;
+use crate::expression::Expression;
+use crate::guide::Guidance;
+use crate::render::{Colors, rasterize, vectorize};
+use crate::schema;
+use crate::tiling::Tiling;
+use crate::weights::Weights;
+use image::imageops::{FilterType, resize};
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+use rand::{Rng, SeedableRng, random};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Cell coordinates as `(row, column)` within the bounding square of the
+/// current diamond.
+pub type Coords = (usize, usize);
+
+/// The direction a domino moves in during shuffling, which also tells its
+/// orientation: `T` and `B` are horizontal, `L` and `R` vertical.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Horizontal domino moving up.
+    T = 1,
+    /// Horizontal domino moving down.
+    B = 2,
+    /// Vertical domino moving left.
+    L = 3,
+    /// Vertical domino moving right.
+    R = 4,
+}
+
+/// A single domino.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Tile {
+    /// Top-left cell covered by the domino.
+    pub pos: Coords,
+    /// Direction of the domino.
+    pub dir: Direction,
+}
+
+/// Aztec diamond grown by domino shuffling.
+///
+/// `data` covers the order `capacity / 2` diamond and stores, for every cell
+/// that is the top-left cell of a domino, the domino's direction. The current
+/// diamond of order `size / 2` is the window starting at `origin`, so tile
+/// coordinates are implicit and growing the diamond only moves the window.
+/// When the window would outgrow `data`, the storage is reallocated with the
+/// diamond re-centered in it.
+///
+/// Saved states follow their own schema, see [`Tiling::to_json`].
+pub struct Diamond {
+    pub(crate) size: usize,
+    pub(crate) capacity: usize,
+    pub(crate) origin: Coords,
+    pub(crate) data: Vec<Option<Direction>>,
+    pub(crate) current_square: Coords,
+    pub(crate) p: f64,
+    pub(crate) rng: Pcg64,
+    pub(crate) weights: Option<Weights>,
+    pub(crate) expression: Option<Expression>,
+    pub(crate) steps: usize,
+}
+
+/// Image whose brightness steers the orientation of created dominoes.
+#[derive(Clone)]
+pub enum EmbeddableImage {
+    /// Image loaded from a file.
+    FileName(String),
+    /// Encoded image held in memory.
+    FileBytes(Vec<u8>),
+}
+
+impl EmbeddableImage {
+    /// Decodes the image and converts it to grayscale.
+    pub fn load(&self) -> Result<GrayImage, TilingError> {
+        Ok(self.decode()?.to_luma8())
+    }
+    /// Decodes the image and converts it to RGB.
+    pub fn load_rgb(&self) -> Result<RgbImage, TilingError> {
+        Ok(self.decode()?.to_rgb8())
+    }
+    fn decode(&self) -> Result<DynamicImage, TilingError> {
+        Ok(match self {
+            EmbeddableImage::FileName(fname) => {
+                image::open(fname).map_err(|source| TilingError::Embed {
+                    path: Some(fname.clone()),
+                    source,
+                })?
+            }
+            EmbeddableImage::FileBytes(data) => image::load_from_memory(data)
+                .map_err(|source| TilingError::Embed { path: None, source })?,
+        })
+    }
+}
+
+/// How the orientation of the pairs created in one step is chosen.
+enum Steer<'a> {
+    /// By the creation probability alone.
+    Probability,
+    /// By brightness bands of an image of the diamond's size: horizontal
+    /// below 128, vertical above 192 and at random in between.
+    Bands(&'a GrayImage),
+    /// By the creation probability pulled towards an image of the
+    /// diamond's size.
+    Guided(&'a GrayImage, &'a Guidance),
+}
+
+impl Diamond {
+    /// Creates an empty diamond.
+    ///
+    /// `p` is the probability of creating a horizontal pair, `size` the side
+    /// of the largest diamond to allocate for (twice its order, rounded up to
+    /// even), `seed` seeds the diamond's random generator and `weights`, if
+    /// given, replaces `p` with weighted shuffling up to the order they were
+    /// computed for with [`Weights::with_order`].
+    pub fn new(p: f64, size: usize, seed: u64, weights: Option<Weights>) -> Diamond {
+        let size = size.next_multiple_of(2);
+        Diamond {
+            size: 0,
+            capacity: size,
+            origin: (size / 2, size / 2),
+            data: vec![None; Diamond::cells(size)],
+            current_square: (0, 0),
+            p,
+            rng: Pcg64::seed_from_u64(seed),
+            weights,
+            expression: None,
+            steps: 0,
+        }
+    }
+    /// Builds a complete diamond of order `order` by asking `direction` for
+    /// the domino whose top-left cell is each uncovered cell in turn, row by
+    /// row, or for `None` to leave the cell empty.
+    pub(crate) fn from_dominoes(
+        order: usize,
+        p: f64,
+        mut direction: impl FnMut(Coords) -> Result<Option<Direction>, TilingError>,
+    ) -> Result<Diamond, TilingError> {
+        let mut x = Diamond::new(p, 2 * order, 0, None);
+        (0..order).for_each(|_| x.extend());
+        for i in 0..x.size {
+            for j in x.span(i) {
+                if x.owner(i, j).is_some() {
+                    continue;
+                }
+                let Some(dir) = direction((i, j))? else {
+                    continue;
+                };
+                let (a, b) = match dir {
+                    Direction::T | Direction::B => (i, j + 1),
+                    Direction::L | Direction::R => (i + 1, j),
+                };
+                if !x.contains(a, b) || x.owner(a, b).is_some() {
+                    return Err(TilingError::Inconsistent(vec![format!(
+                        "domino at ({i}, {j}) pointing {dir:?} does not fit"
+                    )]));
+                }
+                *x.at_ref(i, j) = Some(dir);
+            }
+        }
+        // Where the next search for an empty block starts after a completed
+        // step.
+        x.current_square = (0, order);
+        Ok(x)
+    }
+    /// Replaces `p` and the weights with an expression in the block
+    /// position, the order and the step for all later shuffling steps, or
+    /// goes back to them with `None`. The expression is saved with the
+    /// diamond.
+    pub fn set_expression(&mut self, expression: Option<Expression>) {
+        self.expression = expression;
+    }
+    pub(crate) fn cells(size: usize) -> usize {
+        let n = size / 2;
+        2 * n * (n + 1)
+    }
+    /// Makes room for a diamond of the given size, rounded up to even,
+    /// without further reallocation.
+    pub fn reserve(&mut self, size: usize) {
+        let size = size.next_multiple_of(2);
+        if size <= self.capacity {
+            return;
+        }
+        let tiles: Vec<Tile> = self.tiles().collect();
+        self.capacity = size;
+        self.origin = ((size - self.size) / 2, (size - self.size) / 2);
+        self.data = vec![None; Diamond::cells(size)];
+        tiles.iter().for_each(|tile| {
+            *self.at_ref(tile.pos.0, tile.pos.1) = Some(tile.dir);
+        });
+    }
+    fn to_offset(&self, i: usize, j: usize) -> usize {
+        let j = j - self.half_span(i, self.capacity);
+        let s = self.capacity / 2;
+        if i < s {
+            i * (1 + i) + j
+        } else {
+            let s2 = self.capacity - i;
+            s * 2 * (1 + s) - s2 * (1 + s2) + j
+        }
+    }
+    pub(crate) fn at_ref(&mut self, m: usize, n: usize) -> &mut Option<Direction> {
+        let l = self.to_offset(m + self.origin.0, n + self.origin.1);
+        &mut self.data[l]
+    }
+    pub(crate) fn at(&self, m: usize, n: usize) -> Option<Direction> {
+        self.data[self.to_offset(m + self.origin.0, n + self.origin.1)]
+    }
+    fn get(&self, i: usize, j: usize) -> Option<Direction> {
+        if self.contains(i, j) {
+            self.at(i, j)
+        } else {
+            None
+        }
+    }
+    /// Top-left cell of the domino covering `(i, j)`, if any.
+    pub(crate) fn owner(&self, i: usize, j: usize) -> Option<Coords> {
+        if self.get(i, j).is_some() {
+            Some((i, j))
+        } else if j > 0 && matches!(self.get(i, j - 1), Some(Direction::T | Direction::B)) {
+            Some((i, j - 1))
+        } else if i > 0 && matches!(self.get(i - 1, j), Some(Direction::L | Direction::R)) {
+            Some((i - 1, j))
+        } else {
+            None
+        }
+    }
+    fn is_free(&self, i: usize, j: usize) -> bool {
+        self.owner(i, j).is_none()
+    }
+    fn half_span(&self, i: usize, size: usize) -> usize {
+        let s = size / 2;
+        if i < s { s - 1 - i } else { s + i - size }
+    }
+    pub(crate) fn span(&self, i: usize) -> Range<usize> {
+        let s = self.size / 2;
+        if i < s {
+            Range {
+                start: s - 1 - i,
+                end: self.size - s + 1 + i,
+            }
+        } else {
+            Range {
+                start: s + i - self.size,
+                end: 2 * self.size - s - i,
+            }
+        }
+    }
+    fn extend(&mut self) {
+        if self.size + 2 > self.capacity {
+            self.reserve((self.size + 2).max(2 * self.capacity));
+        }
+        self.size += 2;
+        self.origin.0 -= 1;
+        self.origin.1 -= 1;
+    }
+    fn shrink(&mut self) {
+        self.size -= 2;
+        self.origin.0 += 1;
+        self.origin.1 += 1;
+    }
+    fn find_square(&mut self) -> Option<Coords> {
+        (self.current_square.0..self.size - 1)
+            .find_map(|i| {
+                let Range { start: b, end: e } = self.span(i);
+                ((if i == self.current_square.0 {
+                    self.current_square.1
+                } else {
+                    b
+                })..e - 1)
+                    .find_map(|j| {
+                        if self.is_free(i, j)
+                            && self.is_free(i + 1, j)
+                            && self.is_free(i, j + 1)
+                            && self.is_free(i + 1, j + 1)
+                        {
+                            self.current_square = (i, j);
+                            Some((i, j))
+                        } else {
+                            None
+                        }
+                    })
+            })
+            .or_else(|| {
+                self.current_square = (0, self.span(0).start + 1);
+                None
+            })
+    }
+    fn tile_square(&mut self, c: Coords, steer: &Steer) {
+        let predicate: bool = match steer {
+            Steer::Bands(im) => {
+                let pix = im.get_pixel(c.1 as u32, c.0 as u32).0[0];
+                if pix < 128 {
+                    true
+                } else if (128..=192).contains(&pix) {
+                    let dir: u64 = self.rng.random::<u64>() % 2;
+                    dir == 0
+                } else {
+                    false
+                }
+            }
+            Steer::Guided(im, guidance) => {
+                let brightness = [(0, 0), (0, 1), (1, 0), (1, 1)]
+                    .iter()
+                    .map(|(di, dj)| im.get_pixel((c.1 + dj) as u32, (c.0 + di) as u32).0[0] as f64)
+                    .sum::<f64>()
+                    / 4.0;
+                let p = guidance.probability(
+                    brightness,
+                    self.creation_probability(self.size / 2, self.steps, c),
+                );
+                let dir: f64 = self.rng.random_range(0.0..=1.0);
+                dir < p
+            }
+            Steer::Probability => {
+                let p = self.creation_probability(self.size / 2, self.steps, c);
+                let dir: f64 = self.rng.random_range(0.0..=1.0);
+                dir < p
+            }
+        };
+        if predicate {
+            *self.at_ref(c.0, c.1) = Some(Direction::T);
+            *self.at_ref(c.0 + 1, c.1) = Some(Direction::B);
+        } else {
+            *self.at_ref(c.0, c.1) = Some(Direction::L);
+            *self.at_ref(c.0, c.1 + 1) = Some(Direction::R);
+        }
+    }
+    fn fill_hole(&mut self, c: Coords) {
+        let p = self.hole_probability(self.size / 2, self.steps, c);
+        if self.rng.random_range(0.0..=1.0) < p {
+            *self.at_ref(c.0, c.1) = Some(Direction::B);
+            *self.at_ref(c.0 + 1, c.1) = Some(Direction::T);
+        } else {
+            *self.at_ref(c.0, c.1) = Some(Direction::R);
+            *self.at_ref(c.0, c.1 + 1) = Some(Direction::L);
+        }
+    }
+    fn destroy_blocks(&mut self) {
+        (0..self.size).for_each(|i| {
+            self.span(i).for_each(|j| match self.at(i, j) {
+                Some(Direction::T) if self.get(i + 1, j) == Some(Direction::B) => {
+                    *self.at_ref(i, j) = None;
+                    *self.at_ref(i + 1, j) = None;
+                }
+                Some(Direction::L) if self.get(i, j + 1) == Some(Direction::R) => {
+                    *self.at_ref(i, j) = None;
+                    *self.at_ref(i, j + 1) = None;
+                }
+                _ => {}
+            });
+        });
+    }
+    /// Moves every domino one cell against its direction, the inverse of
+    /// `move_tiles`.
+    fn retract_tiles(&mut self) {
+        let mut above: Vec<Option<Direction>> = vec![None; self.size];
+        (0..self.size).for_each(|i| {
+            let row: Vec<Option<Direction>> = (0..self.size).map(|j| self.get(i, j)).collect();
+            self.span(i).for_each(|j| {
+                *self.at_ref(i, j) = if above[j] == Some(Direction::T) {
+                    Some(Direction::T)
+                } else if self.get(i + 1, j) == Some(Direction::B) {
+                    Some(Direction::B)
+                } else if j > 0 && row[j - 1] == Some(Direction::L) {
+                    Some(Direction::L)
+                } else if row.get(j + 1) == Some(&Some(Direction::R)) {
+                    Some(Direction::R)
+                } else {
+                    None
+                };
+            });
+            above = row;
+        });
+    }
+    fn eliminate_stuck_tiles(&mut self) {
+        (0..self.size).for_each(|i| {
+            self.span(i).for_each(|j| match self.at(i, j) {
+                Some(Direction::B) if self.get(i + 1, j) == Some(Direction::T) => {
+                    *self.at_ref(i, j) = None;
+                    *self.at_ref(i + 1, j) = None;
+                }
+                Some(Direction::R) if self.get(i, j + 1) == Some(Direction::L) => {
+                    *self.at_ref(i, j) = None;
+                    *self.at_ref(i, j + 1) = None;
+                }
+                _ => {}
+            });
+        });
+    }
+    /// Moves every domino one cell in its direction in a single pass over the
+    /// rows, gathering each cell's new value from its four neighbours.
+    fn move_tiles(&mut self) {
+        let mut above: Vec<Option<Direction>> = vec![None; self.size];
+        (0..self.size).for_each(|i| {
+            let row: Vec<Option<Direction>> = (0..self.size).map(|j| self.get(i, j)).collect();
+            self.span(i).for_each(|j| {
+                *self.at_ref(i, j) = if self.get(i + 1, j) == Some(Direction::T) {
+                    Some(Direction::T)
+                } else if above[j] == Some(Direction::B) {
+                    Some(Direction::B)
+                } else if row.get(j + 1) == Some(&Some(Direction::L)) {
+                    Some(Direction::L)
+                } else if j > 0 && row[j - 1] == Some(Direction::R) {
+                    Some(Direction::R)
+                } else {
+                    None
+                };
+            });
+            above = row;
+        });
+    }
+    fn tile(&mut self, steer: Steer) {
+        while let Some(c) = self.find_square() {
+            self.tile_square(c, &steer)
+        }
+    }
+    /// Fails if the creation rule does not reach order `order`, as weights
+    /// computed for a smaller diamond would sample the wrong measure.
+    pub(crate) fn check_order(&self, order: usize) -> Result<(), TilingError> {
+        match (&self.expression, &self.weights) {
+            (None, Some(w)) if order > w.order() => Err(TilingError::Weights(format!(
+                "computed for order {}, cannot reach order {order}",
+                w.order()
+            ))),
+            _ => Ok(()),
+        }
+    }
+    /// Moves every domino and grows the diamond by one order, leaving the
+    /// odd blocks on its new boundary and those freed by annihilation empty.
+    fn advance(&mut self) -> Result<(), TilingError> {
+        self.check_order(self.order() + 1)?;
+        self.steps += 1;
+        self.eliminate_stuck_tiles();
+        self.extend();
+        self.move_tiles();
+        Ok(())
+    }
+    /// Grows the diamond by one order with a single shuffling step, using
+    /// `embed` to steer the newly created dominoes.
+    pub fn step(&mut self, embed: &Option<EmbeddableImage>) -> Result<(), TilingError> {
+        let image = embed.as_ref().map(EmbeddableImage::load).transpose()?;
+        self.advance()?;
+        match image {
+            Some(image) => {
+                let size = self.size as u32;
+                let image = resize(&image, size, size, FilterType::Nearest);
+                self.tile(Steer::Bands(&image));
+            }
+            None => self.tile(Steer::Probability),
+        }
+        Ok(())
+    }
+    /// Grows the diamond by one order with a single shuffling step whose
+    /// creation probabilities follow `image`, resized to the new diamond's
+    /// bounding square, once the step count reaches `guidance.start`.
+    pub fn step_guided(
+        &mut self,
+        image: &GrayImage,
+        guidance: &Guidance,
+    ) -> Result<(), TilingError> {
+        self.advance()?;
+        if self.steps < guidance.start {
+            self.tile(Steer::Probability);
+        } else {
+            let size = self.size as u32;
+            let image = resize(image, size, size, FilterType::Triangle);
+            self.tile(Steer::Guided(&image, guidance));
+        }
+        Ok(())
+    }
+    /// Shrinks the diamond by one order with a reverse shuffling step.
+    ///
+    /// Pairs filling an odd block are destroyed, every other domino moves one
+    /// cell against its direction, and each hole left where forward shuffling
+    /// annihilated a pair is refilled with such a pair at random. Applied to
+    /// a sample of the order `n` measure this yields a sample of the order
+    /// `n - 1` measure.
+    pub fn reverse_step(&mut self) {
+        if self.size == 0 {
+            return;
+        }
+        self.destroy_blocks();
+        self.retract_tiles();
+        self.shrink();
+        self.current_square = (0, (self.size / 2).saturating_sub(1));
+        if self.size > 0 {
+            while let Some(c) = self.find_square() {
+                self.fill_hole(c);
+            }
+        }
+    }
+    /// Replaces the tiling by the order `order` tiling made only of horizontal
+    /// or only of vertical dominoes, the extremal states of the flip chain.
+    pub fn set_extremal(&mut self, order: usize, horizontal: bool) {
+        self.reserve(2 * order);
+        self.data.iter_mut().for_each(|d| *d = None);
+        self.size = 2 * order;
+        self.origin = (
+            (self.capacity - self.size) / 2,
+            (self.capacity - self.size) / 2,
+        );
+        self.current_square = (0, order);
+        // A domino is the first half of its odd block when its top-left cell
+        // is the block's top-left cell, which depends on parity only.
+        (0..self.size).for_each(|a| {
+            self.span(a).step_by(2).for_each(|b| {
+                let first = (a + b + order) % 2 == 1;
+                if horizontal {
+                    *self.at_ref(a, b) = Some(if first { Direction::T } else { Direction::B });
+                } else {
+                    *self.at_ref(b, a) = Some(if first { Direction::L } else { Direction::R });
+                }
+            });
+        });
+    }
+    /// Performs `n` shuffling steps, applying `embed` on the last one and
+    /// calling `progress` with the number of steps done after each.
+    pub fn generate(
+        &mut self,
+        n: usize,
+        embed: Option<EmbeddableImage>,
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), TilingError> {
+        (0..n).try_for_each(|i| {
+            if i == n - 1 {
+                self.step(&embed)?;
+            } else {
+                self.step(&None)?;
+            }
+            progress(i + 1);
+            Ok(())
+        })
+    }
+    /// Performs `n` guided shuffling steps, steering every step from
+    /// `guidance.start` on with `embed`, see [`Diamond::step_guided`], and
+    /// calling `progress` with the number of steps done after each.
+    pub fn generate_guided(
+        &mut self,
+        n: usize,
+        embed: &EmbeddableImage,
+        guidance: &Guidance,
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), TilingError> {
+        let image = embed.load()?;
+        (0..n).try_for_each(|i| {
+            self.step_guided(&image, guidance)?;
+            progress(i + 1);
+            Ok(())
+        })
+    }
+    /// Iterates over all dominoes of the current diamond.
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        (0..self.size).flat_map(move |i| {
+            self.span(i)
+                .filter_map(move |j| self.at(i, j).map(|dir| Tile { pos: (i, j), dir }))
+        })
+    }
+    /// Side of the current diamond's bounding square, twice its order.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Order of the current diamond.
+    pub fn order(&self) -> usize {
+        self.size / 2
+    }
+    /// Whether the cell `(i, j)` lies inside the current diamond.
+    pub fn contains(&self, i: usize, j: usize) -> bool {
+        i < self.size && self.span(i).contains(&j)
+    }
+    /// The domino covering the cell `(i, j)`, if any.
+    pub fn tile_at(&self, i: usize, j: usize) -> Option<Tile> {
+        self.owner(i, j)
+            .and_then(|(a, b)| self.at(a, b).map(|dir| Tile { pos: (a, b), dir }))
+    }
+    /// Restores a diamond serialized with [`Tiling::to_json`], upgrading
+    /// states saved in earlier versions of the schema.
+    ///
+    /// The state is checked with [`Diamond::validate`].
+    pub fn from_json(json: &str) -> Result<Diamond, TilingError> {
+        let x = schema::decode(json)?;
+        x.validate()?;
+        Ok(x)
+    }
+    /// Reads a diamond saved with [`Tiling::save`] or
+    /// [`Diamond::save_binary`], telling them apart by the magic bytes of
+    /// the binary format.
+    pub fn load(path: &str) -> Result<Diamond, TilingError> {
+        let content = std::fs::read(path).map_err(|err| TilingError::io(path, err))?;
+        if content.starts_with(MAGIC) {
+            return Diamond::from_binary(&content);
+        }
+        let content = String::from_utf8(content).map_err(|err| {
+            TilingError::io(
+                path,
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+            )
+        })?;
+        Diamond::from_json(&content)
+    }
+    /// Probability of creating a horizontal pair in the block at `c` when
+    /// shuffling step `step` builds order `order`.
+    pub(crate) fn creation_probability(&self, order: usize, step: usize, c: Coords) -> f64 {
+        match (&self.expression, &self.weights) {
+            (Some(e), _) => e.probability(c, order, step),
+            (None, Some(w)) => w.horizontal_probability(order, c),
+            (None, None) => self.p,
+        }
+    }
+    pub(crate) fn hole_probability(&self, order: usize, step: usize, c: Coords) -> f64 {
+        match (&self.expression, &self.weights) {
+            (Some(e), _) => e.probability(c, order, step),
+            (None, Some(w)) => w.hole_probability(order, c),
+            (None, None) => self.p,
+        }
+    }
+}
+
+impl Tiling for Diamond {
+    /// Rasterizes the tiling with `ts` pixels per cell.
+    fn render(&self, ts: usize, colors: &Colors) -> RgbaImage {
+        let tiles: Vec<Tile> = self.tiles().collect();
+        rasterize(self.size, self.size, &tiles, ts, colors)
+    }
+    /// Draws the tiling as an SVG document with `ts` units per cell, one
+    /// rectangle per tile outlined in the grid color.
+    fn svg(&self, ts: usize, colors: &Colors) -> String {
+        let tiles: Vec<Tile> = self.tiles().collect();
+        vectorize(self.size, self.size, &tiles, ts, colors)
+    }
+    /// Serializes the diamond, including its random generator state, in the
+    /// current version of the schema.
+    ///
+    /// The document holds `format_version`, the `order`, the parameters `p`,
+    /// `steps`, `rng`, `weights` and `expression`, and `rows`, one string per
+    /// row of the diamond with the direction letter of the domino covering
+    /// each cell, or `.` for an empty one.
+    fn to_json(&self) -> Result<String, TilingError> {
+        schema::encode(self)
+    }
+}
+
+pub(crate) fn entropy_rng() -> Pcg64 {
+    Pcg64::seed_from_u64(random())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Number of domino tilings of the order 3 diamond.
+    const TILINGS: usize = 64;
+    /// 99.9% quantile of the chi-squared distribution with `TILINGS - 1`
+    /// degrees of freedom.
+    const CRITICAL: f64 = 103.4;
+    const SAMPLES: u64 = 20_000;
+
+    type Key = Vec<(Coords, u8)>;
+
+    fn key(x: &Diamond) -> Key {
+        x.tiles().map(|t| (t.pos, t.dir as u8)).collect()
+    }
+
+    /// Block weights of a pattern indexed like `Weights`, relative to the
+    /// center of an order `order` diamond.
+    fn weight(pattern: &[Vec<[f64; 4]>], order: usize, tile: Tile) -> f64 {
+        let (i, j) = match tile.dir {
+            Direction::T | Direction::L => tile.pos,
+            Direction::B => (tile.pos.0 - 1, tile.pos.1),
+            Direction::R => (tile.pos.0, tile.pos.1 - 1),
+        };
+        let row =
+            &pattern[(i as isize - order as isize).rem_euclid(pattern.len() as isize) as usize];
+        row[(j as isize - order as isize).rem_euclid(row.len() as isize) as usize]
+            [tile.dir as usize - 1]
+    }
+
+    /// Tallies the order 3 tilings made by `sample` and checks them against
+    /// the measure giving each tiling the product of its domino weights.
+    fn check(pattern: &[Vec<[f64; 4]>], mut sample: impl FnMut(u64) -> Diamond) {
+        let mut counts: HashMap<Key, (usize, f64)> = HashMap::new();
+        (0..SAMPLES).for_each(|seed| {
+            let x = sample(seed);
+            assert_eq!(x.order(), 3);
+            let w = x.tiles().map(|t| weight(pattern, 3, t)).product();
+            counts.entry(key(&x)).or_insert((0, w)).0 += 1;
+        });
+        assert_eq!(counts.len(), TILINGS);
+        let total: f64 = counts.values().map(|(_, w)| w).sum();
+        let chi2: f64 = counts
+            .values()
+            .map(|&(n, w)| {
+                let expected = SAMPLES as f64 * w / total;
+                (n as f64 - expected).powi(2) / expected
+            })
+            .sum();
+        assert!(chi2 < CRITICAL, "chi-squared {chi2} for {pattern:?}");
+    }
+
+    fn grow(p: f64, weights: Option<&str>, order: usize, seed: u64) -> Diamond {
+        let weights = weights.map(|w| {
+            w.parse::<Weights>()
+                .expect("valid weights")
+                .with_order(order)
+        });
+        let mut x = Diamond::new(p, 2 * order, seed, weights);
+        (0..order).for_each(|_| x.step(&None).expect("step within the weights' order"));
+        x
+    }
+
+    #[test]
+    fn odd_sizes_round_up() {
+        let mut x = Diamond::new(0.5, 5, 1, None);
+        x.reserve(7);
+        x.generate(4, None, |_| {}).expect("shuffling");
+        assert!(x.validate().is_ok());
+    }
+
+    #[test]
+    fn step_samples_uniform_measure() {
+        check(&[vec![[1.0; 4]]], |seed| grow(0.5, None, 3, seed));
+    }
+
+    #[test]
+    fn step_samples_biased_measure() {
+        // Horizontal pairs are created with probability tb / (tb + lr).
+        let a = (0.3f64 / 0.7).sqrt();
+        check(&[vec![[a, a, 1.0, 1.0]]], |seed| grow(0.3, None, 3, seed));
+    }
+
+    #[test]
+    fn step_samples_weighted_measure() {
+        let pattern = [vec![[1.0, 2.0, 1.0, 0.5]], vec![[0.5, 1.0, 2.0, 1.0]]];
+        check(&pattern, |seed| {
+            grow(0.5, Some("1:2:1:0.5;0.5:1:2:1"), 3, seed)
+        });
+    }
+
+    #[test]
+    fn reverse_step_samples_uniform_measure() {
+        check(&[vec![[1.0; 4]]], |seed| {
+            let mut x = grow(0.5, None, 4, seed);
+            x.reverse_step();
+            x
+        });
+    }
+
+    #[test]
+    fn reverse_step_samples_weighted_measure() {
+        // A single block pattern keeps its ratios under urban renewal, so
+        // every level samples the same measure.
+        check(&[vec![[1.0, 2.0, 1.0, 0.5]]], |seed| {
+            let mut x = grow(0.5, Some("1:2:1:0.5"), 4, seed);
+            x.reverse_step();
+            x
+        });
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// Problems of an inconsistent state listed before the rest are counted.
+const MAX_PROBLEMS: usize = 20;
+
+/// Everything that can go wrong while loading, growing, rendering or saving a
+/// tiling.
+#[derive(Debug)]
+pub enum TilingError {
+    /// A file could not be read or written.
+    Io {
+        /// The file involved.
+        path: String,
+        /// The underlying failure.
+        source: std::io::Error,
+    },
+    /// A saved state is not valid JSON for a diamond.
+    State(serde_json::Error),
+    /// A binary state is corrupt or of an unsupported version.
+    Binary(String),
+    /// A saved state was read but shuffling cannot continue from it, with
+    /// every problem found.
+    Inconsistent(Vec<String>),
+    /// The image to embed or a mask image could not be opened or decoded.
+    Embed {
+        /// The file name, or `None` for an image given as bytes.
+        path: Option<String>,
+        /// The underlying failure.
+        source: image::ImageError,
+    },
+    /// A rendered image could not be encoded or saved.
+    Image {
+        /// The destination, or `None` when encoding in memory.
+        path: Option<String>,
+        /// The underlying failure.
+        source: image::ImageError,
+    },
+    /// A periodic weight specification could not be parsed.
+    Weights(String),
+    /// A mask does not describe a region that dominoes can tile.
+    Region(String),
+}
+
+impl TilingError {
+    pub(crate) fn io(path: &str, source: std::io::Error) -> TilingError {
+        TilingError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+    /// Whether the error was caused by bad input rather than by the
+    /// environment, e.g. to choose between 4xx and 5xx responses.
+    pub fn is_input_error(&self) -> bool {
+        matches!(
+            self,
+            TilingError::State(_)
+                | TilingError::Binary(_)
+                | TilingError::Inconsistent(_)
+                | TilingError::Embed { .. }
+                | TilingError::Weights(_)
+                | TilingError::Region(_)
+        )
+    }
+}
+
+impl fmt::Display for TilingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TilingError::Io { path, source } => write!(f, "{path}: {source}"),
+            TilingError::State(err) => write!(f, "invalid state: {err}"),
+            TilingError::Binary(err) => write!(f, "invalid binary state: {err}"),
+            TilingError::Inconsistent(problems) => {
+                write!(f, "inconsistent state:")?;
+                problems
+                    .iter()
+                    .take(MAX_PROBLEMS)
+                    .try_for_each(|problem| write!(f, "\n  {problem}"))?;
+                if problems.len() > MAX_PROBLEMS {
+                    write!(f, "\n  and {} more", problems.len() - MAX_PROBLEMS)?;
+                }
+                Ok(())
+            }
+            TilingError::Embed { path, source } => match path {
+                Some(path) => write!(f, "could not load image {path}: {source}"),
+                None => write!(f, "could not decode image to embed: {source}"),
+            },
+            TilingError::Image { path, source } => match path {
+                Some(path) => write!(f, "could not save image {path}: {source}"),
+                None => write!(f, "could not encode image: {source}"),
+            },
+            TilingError::Weights(err) => write!(f, "invalid weights: {err}"),
+            TilingError::Region(err) => write!(f, "invalid region: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TilingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TilingError::Io { source, .. } => Some(source),
+            TilingError::State(err) => Some(err),
+            TilingError::Embed { source, .. } | TilingError::Image { source, .. } => Some(source),
+            TilingError::Binary(_)
+            | TilingError::Inconsistent(_)
+            | TilingError::Weights(_)
+            | TilingError::Region(_) => None,
+        }
+    }
+}
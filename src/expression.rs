@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Arithmetic expression giving the probability of creating a horizontal
+/// pair, in place of a constant `p`.
+///
+/// The variables are `i` and `j`, the row and column of the 2x2 block's
+/// top-left cell within the bounding square of the diamond being built,
+/// `order`, the order being built, and `step`, the number of shuffling steps
+/// the diamond has taken including the current one. The constants `pi` and
+/// `e`, numbers, `+`, `-`, `*`, `/`, `%`, `^` and parentheses are
+/// available, along with the functions `sin`, `cos`, `tan`, `exp`, `ln`,
+/// `sqrt`, `abs`, `floor`, `ceil`, `min` and `max`. Results are clamped to
+/// `[0, 1]`, and an undefined result counts as zero.
+///
+/// Expressions serialize as their source text.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    I,
+    J,
+    Order,
+    Step,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Exp,
+    Ln,
+    Sqrt,
+    Abs,
+    Floor,
+    Ceil,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(f64),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Function, usize)> {
+        Some(match name {
+            "sin" => (Function::Sin, 1),
+            "cos" => (Function::Cos, 1),
+            "tan" => (Function::Tan, 1),
+            "exp" => (Function::Exp, 1),
+            "ln" => (Function::Ln, 1),
+            "sqrt" => (Function::Sqrt, 1),
+            "abs" => (Function::Abs, 1),
+            "floor" => (Function::Floor, 1),
+            "ceil" => (Function::Ceil, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            _ => return None,
+        })
+    }
+    fn apply(self, args: &[f64]) -> f64 {
+        let x = args[0];
+        match self {
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Sqrt => x.sqrt(),
+            Function::Abs => x.abs(),
+            Function::Floor => x.floor(),
+            Function::Ceil => x.ceil(),
+            Function::Min => x.min(args[1]),
+            Function::Max => x.max(args[1]),
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, vars: &[f64; 4]) -> f64 {
+        match self {
+            Node::Number(x) => *x,
+            Node::Variable(v) => vars[*v as usize],
+            Node::Negate(a) => -a.eval(vars),
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vars), b.eval(vars));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    '%' => a.rem_euclid(b),
+                    _ => a.powf(b),
+                }
+            }
+            Node::Call(f, args) => {
+                let args: Vec<f64> = args.iter().map(|a| a.eval(vars)).collect();
+                f.apply(&args)
+            }
+        }
+    }
+}
+
+impl Expression {
+    /// Creation probability for the block with top-left cell `(i, j)`.
+    pub(crate) fn probability(&self, (i, j): (usize, usize), order: usize, step: usize) -> f64 {
+        let p = self
+            .root
+            .eval(&[i as f64, j as f64, order as f64, step as f64]);
+        if p.is_nan() { 0.0 } else { p.clamp(0.0, 1.0) }
+    }
+}
+
+/// Recursive descent parser over the characters of an expression.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.input[self.pos..].chars().find(|c| !c.is_whitespace())
+    }
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() != Some(expected) {
+            return false;
+        }
+        let skipped = self.input[self.pos..].find(expected).unwrap_or_default();
+        self.pos += skipped + expected.len_utf8();
+        true
+    }
+    fn error(&self, message: &str) -> String {
+        format!("{message} at position {} of {:?}", self.pos, self.input)
+    }
+    fn sum(&mut self) -> Result<Node, String> {
+        let mut node = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.eat(op);
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+        Ok(node)
+    }
+    fn product(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.eat(op);
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat('-') {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if self.eat('^') {
+            // Exponentiation binds tighter than negation on its left and
+            // associates to the right.
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+    fn atom(&mut self) -> Result<Node, String> {
+        if self.eat('(') {
+            let node = self.sum()?;
+            if !self.eat(')') {
+                return Err(self.error("expected )"));
+            }
+            return Ok(node);
+        }
+        let rest = self.input[self.pos..].trim_start();
+        self.pos = self.input.len() - rest.len();
+        let mut len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        // The sign of an exponent as in `1e-3` belongs to the number.
+        if rest.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+            && rest[..len].ends_with(['e', 'E'])
+            && rest[len..].starts_with(['+', '-'])
+        {
+            len += 1 + rest[len + 1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len() - len - 1);
+        }
+        if len == 0 {
+            return Err(self.error("expected a number, variable or function"));
+        }
+        let token = &rest[..len];
+        let start = self.pos;
+        self.pos += len;
+        if token.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return token
+                .parse()
+                .map(Node::Number)
+                .map_err(|_| format!("invalid number {token:?} at position {start}"));
+        }
+        let node = match token {
+            "i" => Node::Variable(Variable::I),
+            "j" => Node::Variable(Variable::J),
+            "order" => Node::Variable(Variable::Order),
+            "step" => Node::Variable(Variable::Step),
+            "pi" => Node::Number(std::f64::consts::PI),
+            "e" => Node::Number(std::f64::consts::E),
+            name => {
+                let (function, arity) = Function::from_name(name)
+                    .ok_or_else(|| format!("unknown name {name:?} at position {start}"))?;
+                if !self.eat('(') {
+                    return Err(self.error(&format!("expected ( after {name}")));
+                }
+                let mut args = vec![self.sum()?];
+                while self.eat(',') {
+                    args.push(self.sum()?);
+                }
+                if !self.eat(')') {
+                    return Err(self.error("expected )"));
+                }
+                if args.len() != arity {
+                    return Err(format!(
+                        "{name} takes {arity} argument{}, got {}",
+                        if arity == 1 { "" } else { "s" },
+                        args.len()
+                    ));
+                }
+                Node::Call(function, args)
+            }
+        };
+        Ok(node)
+    }
+}
+
+/// Parses an expression such as `0.5 + 0.4*sin(i/10)`.
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Expression, String> {
+        let mut parser = Parser { input, pos: 0 };
+        let root = parser.sum()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Expression {
+            source: input.to_string(),
+            root,
+        })
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Expression, String> {
+        source.parse()
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> String {
+        expression.source
+    }
+}
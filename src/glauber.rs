@@ -0,0 +1,87 @@
+use crate::diamond::{Diamond, Direction};
+use rand::Rng;
+
+impl Diamond {
+    /// Runs `sweeps` sweeps of the flip Markov chain and returns how many
+    /// flips changed the tiling.
+    ///
+    /// A sweep picks as many uniformly random 2x2 blocks as the diamond has
+    /// cells. A block covered by two parallel dominoes is retiled as a
+    /// horizontal pair with probability `bias`, or by default with the
+    /// probability shuffling would give that block, so that the chain keeps
+    /// the shuffling measure of the current order stationary.
+    pub fn glauber(&mut self, sweeps: usize, bias: Option<f64>) -> usize {
+        if self.size == 0 {
+            return 0;
+        }
+        let cells = self.size * (self.size + 2) / 2;
+        (0..sweeps * cells)
+            .filter(|_| {
+                let (i, j) = loop {
+                    let i = self.rng.random_range(0..self.size - 1);
+                    let j = self.rng.random_range(0..self.size - 1);
+                    if self.block_inside(i, j) {
+                        break (i, j);
+                    }
+                };
+                self.flip(i, j, bias)
+            })
+            .count()
+    }
+    fn block_inside(&self, i: usize, j: usize) -> bool {
+        self.contains(i, j)
+            && self.contains(i + 1, j)
+            && self.contains(i, j + 1)
+            && self.contains(i + 1, j + 1)
+    }
+    /// Resamples the block with top-left cell `(i, j)` if two parallel
+    /// dominoes cover it.
+    fn flip(&mut self, i: usize, j: usize, bias: Option<f64>) -> bool {
+        let horizontal = |d| matches!(d, Some(Direction::T | Direction::B));
+        let vertical = |d| matches!(d, Some(Direction::L | Direction::R));
+        let was_horizontal = if horizontal(self.at(i, j)) && horizontal(self.at(i + 1, j)) {
+            true
+        } else if vertical(self.at(i, j)) && vertical(self.at(i, j + 1)) {
+            false
+        } else {
+            return false;
+        };
+        let order = self.size / 2;
+        // Odd blocks hold created pairs, the others the pairs annihilated by
+        // shuffling, whose directions point at each other.
+        let odd = (i + j + order) % 2 == 1;
+        let p = bias.unwrap_or_else(|| {
+            if odd {
+                self.creation_probability(order, self.steps, (i, j))
+            } else {
+                self.hole_probability(order, self.steps, (i, j))
+            }
+        });
+        let now_horizontal = self.rng.random_range(0.0..=1.0) < p;
+        if now_horizontal == was_horizontal {
+            return false;
+        }
+        *self.at_ref(i, j) = None;
+        *self.at_ref(i + 1, j) = None;
+        *self.at_ref(i, j + 1) = None;
+        match (now_horizontal, odd) {
+            (true, true) => {
+                *self.at_ref(i, j) = Some(Direction::T);
+                *self.at_ref(i + 1, j) = Some(Direction::B);
+            }
+            (true, false) => {
+                *self.at_ref(i, j) = Some(Direction::B);
+                *self.at_ref(i + 1, j) = Some(Direction::T);
+            }
+            (false, true) => {
+                *self.at_ref(i, j) = Some(Direction::L);
+                *self.at_ref(i, j + 1) = Some(Direction::R);
+            }
+            (false, false) => {
+                *self.at_ref(i, j) = Some(Direction::R);
+                *self.at_ref(i, j + 1) = Some(Direction::L);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,50 @@
+/// How an embedded image steers every shuffling step in guided mode, see
+/// [`Diamond::generate_guided`](crate::Diamond::generate_guided).
+///
+/// Brightness maps linearly from `low` to `high` onto a target probability
+/// of creating a horizontal pair, from one for dark blocks to zero for bright
+/// ones, unless `invert` swaps them. The creation probability then moves
+/// from the unguided one towards the target by `strength`.
+#[derive(Clone, Copy, Debug)]
+pub struct Guidance {
+    /// First step, counted from one, on which the image is applied.
+    pub start: usize,
+    /// Weight of the image against the unguided rule, from zero to one.
+    pub strength: f64,
+    /// Brightness at and below which the target probability is one.
+    pub low: u8,
+    /// Brightness at and above which the target probability is zero.
+    pub high: u8,
+    /// Whether bright rather than dark blocks turn horizontal.
+    pub invert: bool,
+}
+
+impl Default for Guidance {
+    fn default() -> Guidance {
+        Guidance {
+            start: 1,
+            strength: 1.0,
+            low: 0,
+            high: 255,
+            invert: false,
+        }
+    }
+}
+
+impl Guidance {
+    /// Creation probability for a block of mean `brightness` where the
+    /// unguided rule would use `p`.
+    pub(crate) fn probability(&self, brightness: f64, p: f64) -> f64 {
+        let (low, high) = (self.low as f64, self.high as f64);
+        let t = if high > low {
+            ((brightness - low) / (high - low)).clamp(0.0, 1.0)
+        } else if brightness > low {
+            1.0
+        } else {
+            0.0
+        };
+        let target = if self.invert { t } else { 1.0 - t };
+        let strength = self.strength.clamp(0.0, 1.0);
+        (1.0 - strength) * p + strength * target
+    }
+}
@@ -0,0 +1,123 @@
+use crate::diamond::{Coords, Diamond};
+use crate::error::TilingError;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{ImageBuffer, ImageError, Luma};
+use std::collections::VecDeque;
+
+/// File formats accepted by [`Diamond::save_height_function`].
+#[derive(Clone, Copy, Debug)]
+pub enum HeightFormat {
+    /// One comma separated row per vertex row, empty outside the diamond.
+    Csv,
+    /// 16-bit grayscale storing `h - min + 1`, zero outside the diamond.
+    Png,
+    /// Little-endian `i32` per vertex, `i32::MIN` outside the diamond.
+    Raw,
+}
+
+impl Diamond {
+    fn cell(&self, i: Option<usize>, j: Option<usize>) -> Option<Coords> {
+        match (i, j) {
+            (Some(i), Some(j)) => self.owner(i, j),
+            _ => None,
+        }
+    }
+    fn crosses_boundary(&self, left: Option<Coords>, right: Option<Coords>) -> bool {
+        match (left, right) {
+            (Some(l), Some(r)) => l != r,
+            (None, None) => false,
+            _ => true,
+        }
+    }
+    /// Thurston height at every lattice vertex, row-major on a
+    /// `(size + 1) x (size + 1)` grid, `None` outside the diamond.
+    ///
+    /// Walking along a domino boundary the height rises by one when the cell
+    /// on the left is black (even `i + j`) and falls by one otherwise. The
+    /// leftmost vertex of the top row has height zero.
+    pub fn height_function(&self) -> Vec<Option<i32>> {
+        let n = self.size + 1;
+        let mut heights: Vec<Option<i32>> = vec![None; n * n];
+        if self.size == 0 {
+            return heights;
+        }
+        let start = self.span(0).start;
+        heights[start] = Some(0);
+        let mut queue = VecDeque::from([(0, start)]);
+        while let Some((a, b)) = queue.pop_front() {
+            let h = heights[a * n + b].unwrap_or_default();
+            let up = a.checked_sub(1);
+            let left = b.checked_sub(1);
+            let sign = if (a + b) % 2 == 0 { 1 } else { -1 };
+            let east = self.crosses_boundary(self.cell(up, Some(b)), self.cell(Some(a), Some(b)));
+            let south =
+                self.crosses_boundary(self.cell(Some(a), Some(b)), self.cell(Some(a), left));
+            let west = left.is_some_and(|l| {
+                self.crosses_boundary(self.cell(up, Some(l)), self.cell(Some(a), Some(l)))
+            });
+            let north = up.is_some_and(|u| {
+                self.crosses_boundary(self.cell(Some(u), Some(b)), self.cell(Some(u), left))
+            });
+            [
+                (east, a, b + 1, -sign),
+                (south, a + 1, b, sign),
+                (west, a, b.wrapping_sub(1), -sign),
+                (north, a.wrapping_sub(1), b, sign),
+            ]
+            .into_iter()
+            .filter(|(edge, ..)| *edge)
+            .for_each(|(_, a, b, dh)| {
+                if heights[a * n + b].is_none() {
+                    heights[a * n + b] = Some(h + dh);
+                    queue.push_back((a, b));
+                }
+            });
+        }
+        heights
+    }
+    /// Writes [`Diamond::height_function`] to `path` in the given format.
+    pub fn save_height_function(
+        &self,
+        format: HeightFormat,
+        path: &str,
+    ) -> Result<(), TilingError> {
+        let n = self.size + 1;
+        let heights = self.height_function();
+        let bytes = match format {
+            HeightFormat::Csv => heights
+                .chunks(n)
+                .map(|row| {
+                    row.iter()
+                        .map(|h| h.map(|h| h.to_string()).unwrap_or_default())
+                        .collect::<Vec<String>>()
+                        .join(",")
+                        + "\n"
+                })
+                .collect::<String>()
+                .into_bytes(),
+            HeightFormat::Raw => heights
+                .iter()
+                .flat_map(|h| h.unwrap_or(i32::MIN).to_le_bytes())
+                .collect(),
+            HeightFormat::Png => {
+                let min = heights.iter().flatten().min().copied().unwrap_or_default();
+                let pixels = heights
+                    .iter()
+                    .map(|h| h.map_or(0, |h| (h - min + 1) as u16))
+                    .collect();
+                let image_error = |source| TilingError::Image {
+                    path: Some(path.to_string()),
+                    source,
+                };
+                let im: ImageBuffer<Luma<u16>, Vec<u16>> =
+                    ImageBuffer::from_raw(n as u32, n as u32, pixels).ok_or_else(|| {
+                        image_error(ImageError::Parameter(ParameterError::from_kind(
+                            ParameterErrorKind::DimensionMismatch,
+                        )))
+                    })?;
+                return im.save(path).map_err(image_error);
+            }
+        };
+        std::fs::write(path, bytes).map_err(|err| TilingError::io(path, err))
+    }
+}
@@ -0,0 +1,59 @@
+//! Random domino tilings of the Aztec diamond.
+//!
+//! A [`Diamond`] is grown one order at a time by domino shuffling, optionally
+//! with a biased, periodically weighted or [`Expression`] driven creation
+//! rule, and can be queried cell by cell, rendered to an in-memory image or
+//! SVG, analysed through its height function or exact placement
+//! probabilities, and saved and restored as JSON or in a compact binary
+//! format.
+//! A [`Region`] holds a tiling of any shape read from a mask, sampled
+//! uniformly by coupling from the past, and a [`Hexagon`] a lozenge tiling
+//! sampled the same way. All of them are drawn and saved through the
+//! [`Tiling`] trait.
+//!
+//! ```no_run
+//! use tilings::{Colors, Diamond, Tiling, TilingError};
+//!
+//! let mut diamond = Diamond::new(0.5, 0, 42, None);
+//! diamond.generate(100, None, |_| {})?;
+//! let image = diamond.render(4, &Colors::default());
+//! let json = diamond.to_json()?;
+//! # Ok::<(), TilingError>(())
+//! ```
+
+#![warn(missing_docs)]
+
+mod animation;
+mod art;
+mod binary;
+mod diamond;
+mod error;
+mod expression;
+mod glauber;
+mod guide;
+mod height;
+mod lozenge;
+mod pips;
+mod probabilities;
+mod region;
+mod render;
+mod schema;
+mod tiling;
+mod validate;
+mod weights;
+
+pub use animation::{Animation, AnimationFormat, AnimationOptions};
+pub use art::Annealing;
+pub use binary::StateFormat;
+pub use diamond::{Coords, Diamond, Direction, EmbeddableImage, Tile};
+pub use error::TilingError;
+pub use expression::Expression;
+pub use guide::Guidance;
+pub use height::HeightFormat;
+pub use lozenge::{Corner, Face, Hexagon, Lozenge};
+pub use pips::{DoubleSix, Placement};
+pub use probabilities::{Ensemble, Marginals};
+pub use region::Region;
+pub use render::{Colors, ImageAction, RenderFormat};
+pub use tiling::Tiling;
+pub use weights::Weights;
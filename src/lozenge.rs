@@ -0,0 +1,285 @@
+use crate::diamond::entropy_rng;
+use crate::error::TilingError;
+use crate::render::Colors;
+use crate::tiling::{Tiling, couple_from_past};
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_hollow_polygon_mut, draw_polygon_mut};
+use imageproc::point::Point;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Point of the three dimensional grid as `(x, y, z)`.
+pub type Corner = (usize, usize, usize);
+
+/// Orientation of a lozenge, named after the side of a cube it shows when
+/// the tiling is seen as a stack of cubes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Face {
+    /// Horizontal face, normal to `z`.
+    Top,
+    /// Face normal to `x`.
+    Left,
+    /// Face normal to `y`.
+    Right,
+}
+
+/// A single lozenge, a unit square of the stepped surface.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Lozenge {
+    /// The corner of the square closest to the origin.
+    pub corner: Corner,
+    /// Orientation of the lozenge.
+    pub face: Face,
+}
+
+/// Lozenge tiling of a hexagon with sides `a`, `b`, `c`, `a`, `b`, `c`.
+///
+/// Tilings are in bijection with stacks of unit cubes in the corner of an
+/// `a x b x c` box, i.e. with plane partitions: `heights` holds, row-major
+/// over the `a x b` floor, how many cubes stand on each cell, and is
+/// non-increasing along rows and columns. The lozenges are the faces of the
+/// stack seen from the direction `(1, 1, 1)`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Hexagon {
+    a: usize,
+    b: usize,
+    c: usize,
+    heights: Vec<usize>,
+    #[serde(default = "entropy_rng")]
+    rng: Pcg64,
+}
+
+impl Hexagon {
+    /// Creates the tiling of an `a x b x c` hexagon given by the empty box.
+    pub fn new(a: usize, b: usize, c: usize, seed: u64) -> Hexagon {
+        Hexagon {
+            a,
+            b,
+            c,
+            heights: vec![0; a * b],
+            rng: Pcg64::seed_from_u64(seed),
+        }
+    }
+    /// Restores a hexagon serialized with [`Tiling::to_json`], checking that
+    /// the heights form a stack of cubes inside the box.
+    pub fn from_json(json: &str) -> Result<Hexagon, TilingError> {
+        let x: Hexagon = serde_json::from_str(json).map_err(TilingError::State)?;
+        let problems = x.problems();
+        if problems.is_empty() {
+            Ok(x)
+        } else {
+            Err(TilingError::Inconsistent(problems))
+        }
+    }
+    /// Descriptions of every way the heights fail to be a plane partition
+    /// inside the `a x b x c` box.
+    fn problems(&self) -> Vec<String> {
+        let (a, b, c) = (self.a, self.b, self.c);
+        if a.checked_mul(b) != Some(self.heights.len()) {
+            return vec![format!(
+                "{} heights stored for a {a}x{b} floor",
+                self.heights.len()
+            )];
+        }
+        let mut problems = Vec::new();
+        (0..a).for_each(|i| {
+            (0..b).for_each(|j| {
+                let h = self.height(i, j);
+                if h > c {
+                    problems.push(format!("{h} cubes on ({i}, {j}) in a box {c} high"));
+                }
+                if i > 0 && h > self.height(i - 1, j) {
+                    problems.push(format!("more cubes on ({i}, {j}) than on ({}, {j})", i - 1));
+                }
+                if j > 0 && h > self.height(i, j - 1) {
+                    problems.push(format!("more cubes on ({i}, {j}) than on ({i}, {})", j - 1));
+                }
+            });
+        });
+        problems
+    }
+    /// Reads a hexagon saved with [`Tiling::save`].
+    pub fn load(path: &str) -> Result<Hexagon, TilingError> {
+        let content = std::fs::read_to_string(path).map_err(|err| TilingError::io(path, err))?;
+        Hexagon::from_json(&content)
+    }
+
+    /// Replaces the tiling with a uniformly random one by coupling from the
+    /// past and returns how many sweeps into the past were needed.
+    ///
+    /// The chain picks a random floor cell and, according to a fair coin,
+    /// adds a cube on it or removes its top cube when the result is still a
+    /// stack. It is monotone for the cellwise order of the heights, so it is
+    /// run from the full and the empty box with the same moves until both end
+    /// in the same stack.
+    pub fn sample(&mut self) -> usize {
+        let cells = self.a * self.b;
+        if cells == 0 || self.c == 0 {
+            return 0;
+        }
+        let (a, b, c) = (self.a, self.b, self.c);
+        let (heights, sweeps) = couple_from_past(
+            &mut self.rng,
+            cells,
+            vec![c; cells],
+            vec![0; cells],
+            |rng, upper, lower| {
+                let cell = (rng.random_range(0..a), rng.random_range(0..b));
+                let up = rng.random::<bool>();
+                Hexagon::flip((a, b, c), upper, cell, up);
+                Hexagon::flip((a, b, c), lower, cell, up);
+            },
+        );
+        self.heights = heights;
+        sweeps
+    }
+
+    /// Side lengths `(a, b, c)` of the hexagon.
+    pub fn sides(&self) -> (usize, usize, usize) {
+        (self.a, self.b, self.c)
+    }
+    /// Number of cubes stacked on floor cell `(i, j)`.
+    pub fn height(&self, i: usize, j: usize) -> usize {
+        self.heights[i * self.b + j]
+    }
+    /// Iterates over all `ab + bc + ca` lozenges of the tiling.
+    pub fn lozenges(&self) -> impl Iterator<Item = Lozenge> + '_ {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let top = (0..a).flat_map(move |i| {
+            (0..b).map(move |j| Lozenge {
+                corner: (i, j, self.height(i, j)),
+                face: Face::Top,
+            })
+        });
+        // The front face of each row of cubes along `x`, or the back wall
+        // where the row is empty, and likewise along `y`.
+        let left = (0..b).flat_map(move |j| {
+            (0..c).map(move |z| Lozenge {
+                corner: ((0..a).filter(|&i| self.height(i, j) > z).count(), j, z),
+                face: Face::Left,
+            })
+        });
+        let right = (0..a).flat_map(move |i| {
+            (0..c).map(move |z| Lozenge {
+                corner: (i, (0..b).filter(|&j| self.height(i, j) > z).count(), z),
+                face: Face::Right,
+            })
+        });
+        top.chain(left).chain(right)
+    }
+
+    /// Adds a cube on `cell` if `up` and removes one otherwise, when the
+    /// heights stay a plane partition bounded by `c`.
+    fn flip(
+        (a, b, c): (usize, usize, usize),
+        heights: &mut [usize],
+        (i, j): (usize, usize),
+        up: bool,
+    ) -> bool {
+        let at = |i: usize, j: usize| heights[i * b + j];
+        let h = at(i, j);
+        let allowed = if up {
+            h < c && (i == 0 || h < at(i - 1, j)) && (j == 0 || h < at(i, j - 1))
+        } else {
+            h > 0 && (i + 1 == a || h > at(i + 1, j)) && (j + 1 == b || h > at(i, j + 1))
+        };
+        if allowed {
+            heights[i * b + j] = if up { h + 1 } else { h - 1 };
+        }
+        allowed
+    }
+    /// Screen position of a point with lozenge sides of length `side`: `x`
+    /// points down left, `y` down right and `z` up.
+    fn project(&self, (x, y, z): Corner, side: f32) -> (f32, f32) {
+        let h = 3f32.sqrt() / 2.0;
+        (
+            (self.a as f32 + y as f32 - x as f32) * h * side,
+            (self.c as f32 + (x + y) as f32 / 2.0 - z as f32) * side,
+        )
+    }
+    /// Vertices of `lozenge` on screen.
+    fn polygon(&self, lozenge: &Lozenge, side: f32) -> [(f32, f32); 4] {
+        let (x, y, z) = lozenge.corner;
+        match lozenge.face {
+            Face::Top => [(x, y, z), (x + 1, y, z), (x + 1, y + 1, z), (x, y + 1, z)],
+            Face::Left => [(x, y, z), (x, y + 1, z), (x, y + 1, z + 1), (x, y, z + 1)],
+            Face::Right => [(x, y, z), (x + 1, y, z), (x + 1, y, z + 1), (x, y, z + 1)],
+        }
+        .map(|p| self.project(p, side))
+    }
+    /// Size of the picture of the hexagon with lozenge sides `side`.
+    fn extent(&self, side: f32) -> (u32, u32) {
+        let h = 3f32.sqrt() / 2.0;
+        (
+            ((self.a + self.b) as f32 * h * side).ceil() as u32 + 1,
+            (((self.a + self.b) as f32 / 2.0 + self.c as f32) * side).ceil() as u32 + 1,
+        )
+    }
+    fn color(face: Face, colors: &Colors) -> Rgba<u8> {
+        match face {
+            Face::Top => colors.top,
+            Face::Left => colors.left,
+            Face::Right => colors.right,
+        }
+    }
+}
+
+impl Tiling for Hexagon {
+    /// Rasterizes the tiling with lozenge sides of `2 * ts` pixels, the
+    /// length of a domino, drawing top faces in the top color and the two
+    /// other orientations in the left and right colors.
+    fn render(&self, ts: usize, colors: &Colors) -> RgbaImage {
+        let side = 2.0 * ts as f32;
+        let (width, height) = self.extent(side);
+        let mut im = RgbaImage::from_pixel(width, height, Rgba([128, 128, 128, 255]));
+        self.lozenges().for_each(|lozenge| {
+            let polygon = self.polygon(&lozenge, side);
+            draw_polygon_mut(
+                &mut im,
+                &polygon.map(|(u, v)| Point::new(u.round() as i32, v.round() as i32)),
+                Hexagon::color(lozenge.face, colors),
+            );
+            draw_hollow_polygon_mut(
+                &mut im,
+                &polygon.map(|(u, v)| Point::new(u, v)),
+                colors.grid,
+            );
+        });
+        im
+    }
+    /// Draws the tiling as an SVG document with lozenge sides of `2 * ts`
+    /// units.
+    fn svg(&self, ts: usize, colors: &Colors) -> String {
+        let side = 2.0 * ts as f32;
+        let (width, height) = self.extent(side);
+        let grid = Colors::to_svg(colors.grid);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\">\n\
+             <rect width=\"{width}\" height=\"{height}\" fill=\"#808080\"/>\n\
+             <g stroke=\"{grid}\" stroke-width=\"1\" stroke-linejoin=\"round\">\n"
+        );
+        self.lozenges().for_each(|lozenge| {
+            let points: Vec<String> = self
+                .polygon(&lozenge, side)
+                .iter()
+                .map(|(u, v)| format!("{u:.2},{v:.2}"))
+                .collect();
+            let _ = writeln!(
+                svg,
+                "<polygon points=\"{}\" fill=\"{}\"/>",
+                points.join(" "),
+                Colors::to_svg(Hexagon::color(lozenge.face, colors))
+            );
+        });
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+    /// Serializes the hexagon, its stack of cubes and its random generator
+    /// state.
+    fn to_json(&self) -> Result<String, TilingError> {
+        serde_json::to_string(self).map_err(TilingError::State)
+    }
+}
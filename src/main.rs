@@ -1,689 +1,884 @@
-use actix_extract_multipart::*;
-use actix_web::{App, HttpResponse, HttpServer, get, http::header, post, web};
-use clap::Parser;
-use image::imageops::{FilterType, resize};
-use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
-use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut};
-use imageproc::rect::Rect;
+mod web;
+
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use progressing::{Baring, mapping::Bar as MappingBar};
-use rand::{Rng, random};
-use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::io::Cursor;
+use rand::{Rng, SeedableRng, random};
+use rand_pcg::Pcg64;
 use std::num::ParseIntError;
-use std::ops::Range;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use tilings::{
+    Animation, AnimationFormat, AnimationOptions, Annealing, Colors, Diamond, Direction, DoubleSix,
+    EmbeddableImage, Ensemble, Expression, Guidance, HeightFormat, Hexagon, ImageAction, Region,
+    RenderFormat, StateFormat, Tile, Tiling, TilingError, Weights,
+};
 
-type Coords = (usize, usize);
+fn parse_hex(input: &str) -> Result<u32, ParseIntError> {
+    u32::from_str_radix(input, 16)
+}
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
-enum Direction {
-    T = 1,
-    B = 2,
-    L = 3,
-    R = 4,
+fn parse_sides(input: &str) -> Result<(usize, usize, usize), String> {
+    let sides = input
+        .split(['x', ','])
+        .map(|side| side.trim().parse::<usize>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match sides[..] {
+        [a, b, c] => Ok((a, b, c)),
+        _ => Err("expected three sides as AxBxC".to_string()),
+    }
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
-struct Tile {
-    pos: Coords,
-    dir: Direction,
+#[derive(Parser)]
+#[command(version = "1.0", author = "Abbath")]
+struct Opts {
+    #[command(subcommand)]
+    command: Command,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Diamond {
-    size: usize,
-    capacity: usize,
-    origin: Coords,
-    data: Vec<usize>,
-    tiles: HashMap<usize, Tile>,
-    tile_id: usize,
-    free_ids: VecDeque<usize>,
-    current_square: Coords,
-    p: f64,
+/// Where and how an image is drawn.
+#[derive(Args)]
+struct Style {
+    #[arg(short, long, default_value = "test.png")]
+    filename: String,
+    #[arg(long)]
+    format: Option<RenderFormat>,
+    #[arg(short('s'), long, default_value = "8")]
+    tile_size: usize,
+    #[arg(short, long, default_value = "ff0000ff", value_parser = parse_hex)]
+    top_color: u32,
+    #[arg(short, long, default_value = "0000ffff", value_parser = parse_hex)]
+    bottom_color: u32,
+    #[arg(short, long, default_value = "ffff00ff", value_parser = parse_hex)]
+    left_color: u32,
+    #[arg(short, long, default_value = "00ff00ff", value_parser = parse_hex)]
+    right_color: u32,
+    #[arg(short, long, default_value = "000000ff", value_parser = parse_hex)]
+    grid_color: u32,
+    #[arg(short('c'), long)]
+    random_colors: bool,
 }
 
-enum ImageAction {
-    Save(String),
-    Return,
+/// The creation rule of domino shuffling.
+#[derive(Args)]
+struct Shuffling {
+    #[arg(short('p'), long, default_value = "0.5")]
+    probability: f64,
+    #[arg(short('W'), long)]
+    weights: Option<Weights>,
+    #[arg(long)]
+    probability_expr: Option<Expression>,
+    #[arg(short('e'), long)]
+    embed: Option<String>,
+    #[arg(long, requires = "embed")]
+    guided: bool,
+    #[arg(long, default_value = "1")]
+    guide_start: usize,
+    #[arg(long, default_value = "1")]
+    guide_strength: f64,
+    #[arg(long, default_value = "0")]
+    guide_low: u8,
+    #[arg(long, default_value = "255")]
+    guide_high: u8,
+    #[arg(long)]
+    guide_invert: bool,
 }
 
-#[derive(Clone)]
-enum EmbeddableImage {
-    FileName(String),
-    FileBytes(Vec<u8>),
+#[derive(Subcommand)]
+enum Command {
+    /// Grow a diamond by domino shuffling, or sample a region or a hexagon, and save its state
+    Generate {
+        #[arg(short('n'), long, default_value = "256")]
+        steps: usize,
+        #[arg(short('i'), long)]
+        input: Option<String>,
+        #[arg(long, default_value = "0")]
+        reverse_steps: usize,
+        #[arg(short('S'), long)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        shuffling: Shuffling,
+        #[arg(long, conflicts_with_all = ["input", "hexagon"])]
+        mask: Option<String>,
+        #[arg(long, value_parser = parse_sides, conflicts_with = "input")]
+        hexagon: Option<(usize, usize, usize)>,
+        /// State file, in the binary format if it ends in .bin, or - for JSON on standard output,
+        /// the default unless the animation goes there
+        #[arg(short('o'), long)]
+        output: Option<String>,
+        #[arg(short('a'), long)]
+        save_all_steps: bool,
+        #[arg(long)]
+        animation: Option<String>,
+        #[arg(long, default_value = "100")]
+        frame_delay: u16,
+        #[arg(long, default_value = "0")]
+        loop_count: u16,
+        #[arg(long, default_value = "0")]
+        frame_skip: usize,
+        #[command(flatten)]
+        style: Style,
+    },
+    /// Draw a saved diamond, region or hexagon
+    Render {
+        state: String,
+        #[command(flatten)]
+        style: Style,
+        /// Seed of --random-colors
+        #[arg(short('S'), long)]
+        seed: Option<u64>,
+        #[arg(long, requires = "embed")]
+        pips: Option<String>,
+        #[arg(short('e'), long, requires = "pips")]
+        embed: Option<String>,
+        #[arg(long)]
+        pips_invert: bool,
+    },
+    /// Report on a saved diamond, export its height function, or compute placement probabilities or sampled densities
+    Stats {
+        #[arg(required_unless_present_any = ["samples", "probabilities_csv", "probabilities_png"])]
+        state: Option<String>,
+        /// Order of a new diamond, or steps past the order of the state
+        #[arg(short('n'), long)]
+        steps: Option<usize>,
+        #[arg(short('S'), long)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        shuffling: Shuffling,
+        #[arg(long, requires = "state")]
+        height_csv: Option<String>,
+        #[arg(long, requires = "state")]
+        height_png: Option<String>,
+        #[arg(long, requires = "state")]
+        height_raw: Option<String>,
+        #[arg(long)]
+        probabilities_csv: Option<String>,
+        #[arg(long)]
+        probabilities_png: Option<String>,
+        #[arg(long, conflicts_with = "state")]
+        samples: Option<usize>,
+        #[command(flatten)]
+        style: Style,
+    },
+    /// Convert a diamond state between JSON and the binary format
+    Convert {
+        input: String,
+        output: String,
+        /// Format of the output, by default the binary one if it ends in .bin
+        #[arg(long)]
+        to: Option<StateFormat>,
+    },
+    /// Serve tilings over HTTP
+    Serve {
+        #[arg(long, default_value = "0.0.0.0")]
+        address: String,
+        #[arg(long, default_value = "3000")]
+        port: u16,
+        /// Worker threads, by default one per physical core
+        #[arg(long)]
+        workers: Option<usize>,
+        /// Most shuffling steps a request may ask for
+        #[arg(long, default_value = "256")]
+        max_steps: usize,
+        /// Longest hexagon side a request may ask for
+        #[arg(long, default_value = "32")]
+        max_side: usize,
+        /// Most blocks in one period of a weight pattern a request may ask for
+        #[arg(long, default_value = "64")]
+        max_weight_period: usize,
+        /// Largest tile size in pixels a request may ask for
+        #[arg(long, default_value = "8")]
+        max_tile_size: usize,
+        /// Largest request body in bytes
+        #[arg(long, default_value = "10485760")]
+        max_upload: usize,
+    },
+    /// Run the flip Markov chain on the input state or on a new diamond
+    Glauber {
+        #[arg(long, default_value = "1")]
+        sweeps: usize,
+        #[arg(long)]
+        bias: Option<f64>,
+        #[arg(long)]
+        extremal: Option<Extremal>,
+        #[arg(long)]
+        trace: Option<String>,
+        #[arg(short('n'), long, default_value = "256")]
+        steps: usize,
+        #[arg(short('i'), long)]
+        input: Option<String>,
+        #[arg(short('S'), long)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        shuffling: Shuffling,
+        #[arg(short('o'), long)]
+        output: Option<String>,
+        #[command(flatten)]
+        style: Style,
+    },
+    /// Approximate a target image with the colors of a tiling by simulated annealing
+    Art {
+        target: String,
+        #[arg(long, default_value = "rectangle")]
+        shape: Shape,
+        #[arg(long, default_value = "64")]
+        width: usize,
+        #[arg(long, default_value = "64")]
+        height: usize,
+        #[arg(long, default_value = "100")]
+        iterations: usize,
+        #[arg(long, default_value = "1")]
+        start_temperature: f64,
+        #[arg(long, default_value = "0.01")]
+        end_temperature: f64,
+        #[arg(long)]
+        log: Option<String>,
+        #[arg(short('S'), long)]
+        seed: Option<u64>,
+        #[arg(long)]
+        pips: Option<String>,
+        #[arg(long)]
+        pips_invert: bool,
+        #[arg(short('o'), long)]
+        output: Option<String>,
+        #[command(flatten)]
+        style: Style,
+    },
+    /// Check a saved diamond state, JSON or binary, and list every inconsistency
+    Verify { state: String },
 }
 
-impl Diamond {
-    pub fn new(p: f64, size: usize) -> Diamond {
-        let n = size / 2 - 1;
-        let corner = n * (n + 1) / 2;
-        Diamond {
-            size: 0,
-            capacity: size,
-            origin: (size / 2, size / 2),
-            data: vec![0; size * size - corner * 4],
-            tiles: HashMap::new(),
-            tile_id: 1,
-            free_ids: VecDeque::new(),
-            current_square: (0, 0),
-            p,
-        }
-    }
-    fn to_offset(&self, i: usize, j: usize) -> usize {
-        let j = j - self.half_span(i, self.capacity);
-        let s = self.capacity / 2;
-        if i < s {
-            i * (1 + i) + j
+/// Region tiled by the art command: a `width x height` rectangle or the
+/// Aztec diamond inscribed in a `width x width` square.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Shape {
+    Rectangle,
+    Diamond,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Extremal {
+    Horizontal,
+    Vertical,
+}
+
+fn random_color(rng: &mut impl Rng) -> u32 {
+    let r: u8 = rng.random();
+    let g: u8 = rng.random();
+    let b: u8 = rng.random();
+    let a: u8 = 255;
+    ((r as u32) << 24) | ((g as u32) << 16) | ((b as u32) << 8) | a as u32
+}
+
+impl Style {
+    /// The requested colors, drawn from `seed` with `--random-colors`.
+    fn colors(&self, seed: u64) -> Colors {
+        if self.random_colors {
+            let mut rng = Pcg64::seed_from_u64(seed);
+            Colors::new(
+                random_color(&mut rng),
+                random_color(&mut rng),
+                random_color(&mut rng),
+                random_color(&mut rng),
+                random_color(&mut rng),
+            )
         } else {
-            let s2 = self.capacity - i;
-            s * 2 * (1 + s) - s2 * (1 + s2) + j
+            Colors::new(
+                self.top_color,
+                self.bottom_color,
+                self.left_color,
+                self.right_color,
+                self.grid_color,
+            )
         }
     }
-    fn at_ref(&mut self, m: usize, n: usize) -> &mut usize {
-        let l = self.to_offset(m + self.origin.0, n + self.origin.1);
-        &mut self.data[l]
-    }
-    fn at(&self, m: usize, n: usize) -> usize {
-        self.data[self.to_offset(m + self.origin.0, n + self.origin.1)]
-    }
-    fn clear_square(&mut self, i: usize, j: usize) {
-        *self.at_ref(i, j) = 0;
-        *self.at_ref(i + 1, j) = 0;
-        *self.at_ref(i, j + 1) = 0;
-        *self.at_ref(i + 1, j + 1) = 0;
+    fn format(&self) -> RenderFormat {
+        self.format
+            .unwrap_or_else(|| RenderFormat::from_path(&self.filename))
     }
-    fn half_span(&self, i: usize, size: usize) -> usize {
-        let s = size / 2;
-        if i < s { s - 1 - i } else { s + i - size }
+    /// Saves the image of `x` to the requested file.
+    fn draw(&self, x: &dyn Tiling, colors: &Colors) -> Result<(), TilingError> {
+        x.draw_image(
+            self.tile_size,
+            colors,
+            ImageAction::Save(self.filename.clone(), self.format()),
+        )?;
+        Ok(())
     }
-    fn span(&self, i: usize) -> Range<usize> {
-        let s = self.size / 2;
-        if i < s {
-            Range {
-                start: s - 1 - i,
-                end: self.size - s + 1 + i,
-            }
-        } else {
-            Range {
-                start: s + i - self.size,
-                end: 2 * self.size - s - i,
-            }
+}
+
+impl Shuffling {
+    fn guidance(&self) -> Guidance {
+        Guidance {
+            start: self.guide_start,
+            strength: self.guide_strength,
+            low: self.guide_low,
+            high: self.guide_high,
+            invert: self.guide_invert,
         }
     }
-    fn extend(&mut self) {
-        self.size += 2;
-        self.origin.0 -= 1;
-        self.origin.1 -= 1;
-        self.tiles.par_iter_mut().for_each(|(_, tile)| {
-            tile.pos = (tile.pos.0 + 1, tile.pos.1 + 1);
-        });
-    }
-    fn find_square(&mut self) -> Option<Coords> {
-        (self.current_square.0..self.size - 1)
-            .find_map(|i| {
-                let Range { start: b, end: e } = self.span(i);
-                ((if i == self.current_square.0 {
-                    self.current_square.1
-                } else {
-                    b
-                })..e - 1)
-                    .find_map(|j| {
-                        if self.at(i, j) == 0
-                            && self.at(i + 1, j) == 0
-                            && self.at(i, j + 1) == 0
-                            && self.at(i + 1, j + 1) == 0
-                        {
-                            self.current_square = (i, j);
-                            Some((i, j))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .or_else(|| {
-                self.current_square = (0, self.span(0).start + 1);
-                None
-            })
-    }
-    fn next_tile_id(&mut self) -> usize {
-        self.free_ids.pop_front().unwrap_or({
-            let tid = self.tile_id;
-            self.tile_id += 1;
-            tid
-        })
+    /// Reads `input`, or creates an empty diamond with room for `steps`
+    /// steps and weights computed for order `steps`, and applies the
+    /// expression if one is given.
+    fn diamond(
+        &self,
+        input: Option<&str>,
+        steps: usize,
+        seed: u64,
+    ) -> Result<Diamond, TilingError> {
+        let mut x = match input {
+            Some(input) => Diamond::load(input)?,
+            None => Diamond::new(
+                self.probability,
+                steps * 2,
+                seed,
+                self.weights.clone().map(|w| w.with_order(steps)),
+            ),
+        };
+        if let Some(expression) = &self.probability_expr {
+            x.set_expression(Some(expression.clone()));
+        }
+        Ok(x)
     }
-    fn tile_square(&mut self, c: Coords, img: &Option<image::DynamicImage>) {
-        let mut rng = rand::thread_rng();
-        let predicate: bool = match img {
-            Some(im) => {
-                let pix = im.get_pixel(c.1 as u32, c.0 as u32).0[0];
-                if pix < 128 {
-                    true
-                } else if (128..=192).contains(&pix) {
-                    let dir: u64 = rng.r#gen::<u64>() % 2;
-                    dir == 0
-                } else {
-                    false
-                }
-            }
-            None => {
-                let dir: f64 = rng.gen_range(0.0..=1.0);
-                dir < self.p
+    /// Runs `steps` shuffling steps, guided by the embedded image if asked
+    /// to.
+    fn grow(&self, x: &mut Diamond, steps: usize) -> Result<(), TilingError> {
+        let embed = self.embed.clone().map(EmbeddableImage::FileName);
+        match embed {
+            Some(embed) if self.guided => {
+                x.generate_guided(steps, &embed, &self.guidance(), progress_bar(steps))
             }
-        };
-        let tid = self.next_tile_id();
-        *self.at_ref(c.0, c.1) = tid;
-        if predicate {
-            *self.at_ref(c.0, c.1 + 1) = tid;
-            self.tiles.insert(
-                tid,
-                Tile {
-                    pos: c,
-                    dir: Direction::T,
-                },
-            );
-            let tid = self.next_tile_id();
-            *self.at_ref(c.0 + 1, c.1) = tid;
-            *self.at_ref(c.0 + 1, c.1 + 1) = tid;
-            self.tiles.insert(
-                tid,
-                Tile {
-                    pos: (c.0 + 1, c.1),
-                    dir: Direction::B,
-                },
-            );
-        } else {
-            *self.at_ref(c.0 + 1, c.1) = tid;
-            self.tiles.insert(
-                tid,
-                Tile {
-                    pos: c,
-                    dir: Direction::L,
-                },
-            );
-            let tid = self.next_tile_id();
-            *self.at_ref(c.0, c.1 + 1) = tid;
-            *self.at_ref(c.0 + 1, c.1 + 1) = tid;
-            self.tiles.insert(
-                tid,
-                Tile {
-                    pos: (c.0, c.1 + 1),
-                    dir: Direction::R,
-                },
-            );
+            embed => x.generate(steps, embed, progress_bar(steps)),
         }
     }
-    fn remove_two_tiles(&mut self, tid1: usize, tid2: usize, i: usize, j: usize) {
-        self.tiles.remove(&tid1);
-        self.tiles.remove(&tid2);
-        self.clear_square(i, j);
-        self.free_ids.push_back(tid1);
-        self.free_ids.push_back(tid2);
+}
+
+fn main() -> ExitCode {
+    let opts: Opts = Opts::parse();
+    if let Command::Generate {
+        output: Some(output),
+        animation: Some(animation),
+        ..
+    } = &opts.command
+        && output == "-"
+        && animation == "-"
+    {
+        Opts::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the state and the animation cannot both go to standard output",
+            )
+            .exit();
     }
-    fn eliminate_stuck_tiles(&mut self) {
-        if self.size == 0 {
-            return;
+    match run(opts.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
         }
-        (0..self.size - 1).for_each(|i| {
-            let Range { start: b, end: e } = self.span(i);
-            (b..e - 1).for_each(|j| {
-                if self.at(i, j) > 0 {
-                    let tile_id = self.at(i, j);
-                    if self.tiles[&tile_id].dir == Direction::B && j > b && self.at(i + 1, j) > 0 {
-                        let tile_id_2 = self.at(i + 1, j);
-                        if self.tiles[&tile_id_2].dir == Direction::T {
-                            self.remove_two_tiles(tile_id, tile_id_2, i, j)
-                        }
-                    } else if self.tiles[&tile_id].dir == Direction::R && self.at(i, j + 1) > 0 {
-                        let tile_id_2 = self.at(i, j + 1);
-                        if self.tiles[&tile_id_2].dir == Direction::L {
-                            self.remove_two_tiles(tile_id, tile_id_2, i, j)
-                        }
-                    }
-                }
-            });
-        });
     }
-    fn move_tiles(&mut self) {
-        let to_move: Vec<(usize, Tile)> =
-            self.tiles.iter().map(|(id, tile)| (*id, *tile)).collect();
-        self.tiles.par_iter_mut().for_each(|(_, tile)| {
-            let c = tile.pos;
-            match tile.dir {
-                Direction::T => {
-                    tile.pos = (c.0 - 1, c.1);
-                }
-                Direction::B => {
-                    tile.pos = (c.0 + 1, c.1);
-                }
-                Direction::L => {
-                    tile.pos = (c.0, c.1 - 1);
-                }
-                Direction::R => {
-                    tile.pos = (c.0, c.1 + 1);
+}
+
+fn run(command: Command) -> Result<(), TilingError> {
+    match command {
+        Command::Generate {
+            steps,
+            input,
+            reverse_steps,
+            seed,
+            shuffling,
+            mask,
+            hexagon,
+            output,
+            save_all_steps,
+            animation,
+            frame_delay,
+            loop_count,
+            frame_skip,
+            style,
+        } => {
+            let seed = seed.unwrap_or_else(random);
+            let output = match (output, animation.as_deref()) {
+                (None, Some("-")) => None,
+                (output, _) => Some(output.unwrap_or_else(|| "-".to_string())),
+            };
+            if let Some(mask) = mask {
+                let mut region = Region::load_mask(&mask, seed)?;
+                let holes = region.holes();
+                if holes > 0 {
+                    eprintln!(
+                        "warning: the region has {holes} holes, sampling only among tilings reachable by flips"
+                    );
                 }
+                eprintln!("Sampling...");
+                let sweeps = region.sample();
+                eprintln!("Coalesced after {sweeps} sweeps");
+                return output.map_or(Ok(()), |output| write_state(&region, &output));
             }
-        });
-        to_move.iter().for_each(|(id, tile)| {
-            let Tile {
-                pos: (i, j),
-                dir: o,
-            } = tile;
-            match o {
-                Direction::T => {
-                    if self.at(*i, *j) == *id {
-                        *self.at_ref(*i, *j) = 0;
-                    }
-                    if self.at(*i, *j + 1) == *id {
-                        *self.at_ref(*i, j + 1) = 0;
+            if let Some((a, b, c)) = hexagon {
+                let mut hexagon = Hexagon::new(a, b, c, seed);
+                eprintln!("Sampling...");
+                let sweeps = hexagon.sample();
+                eprintln!("Coalesced after {sweeps} sweeps");
+                return output.map_or(Ok(()), |output| write_state(&hexagon, &output));
+            }
+            let mut x = shuffling.diamond(input.as_deref(), steps, seed)?;
+            (0..reverse_steps).for_each(|_| x.reverse_step());
+            x.reserve(x.size() + steps * 2);
+            if save_all_steps || animation.is_some() {
+                let colors = style.colors(seed);
+                let format = style.format();
+                let every = frame_skip + 1;
+                let mut animation = animation
+                    .as_deref()
+                    .map(|path| {
+                        Animation::create(
+                            path,
+                            AnimationFormat::from_path(path),
+                            x.size() + steps * 2,
+                            style.tile_size,
+                            steps.div_ceil(every),
+                            AnimationOptions {
+                                delay: frame_delay,
+                                loops: loop_count,
+                            },
+                        )
+                    })
+                    .transpose()?;
+                let guide = match &shuffling.embed {
+                    Some(embed) if shuffling.guided => Some((
+                        EmbeddableImage::FileName(embed.clone()).load()?,
+                        shuffling.guidance(),
+                    )),
+                    _ => None,
+                };
+                for i in 0..steps {
+                    eprintln!("Step {i}");
+                    match &guide {
+                        Some((image, guidance)) => x.step_guided(image, guidance)?,
+                        None => x.step(&None)?,
                     }
-                    *self.at_ref(i - 1, *j) = *id;
-                    *self.at_ref(i - 1, j + 1) = *id;
-                }
-                Direction::B => {
-                    if self.at(*i, *j) == *id {
-                        *self.at_ref(*i, *j) = 0;
+                    if save_all_steps {
+                        eprintln!("Saving image {i}");
+                        x.draw_image(
+                            style.tile_size,
+                            &colors,
+                            ImageAction::Save(
+                                format!(
+                                    "{}_{:0width$}.{}",
+                                    style.filename,
+                                    i + 1,
+                                    format.extension(),
+                                    width = steps.ilog10() as usize + 1
+                                ),
+                                format,
+                            ),
+                        )?;
                     }
-                    if self.at(*i, j + 1) == *id {
-                        *self.at_ref(*i, j + 1) = 0;
+                    if let Some(animation) = animation.as_mut()
+                        && ((i + 1) % every == 0 || i + 1 == steps)
+                    {
+                        eprintln!("Adding frame {i}");
+                        animation.add_frame(&x, &colors)?;
                     }
-                    *self.at_ref(i + 1, *j) = *id;
-                    *self.at_ref(i + 1, j + 1) = *id;
                 }
-                Direction::L => {
-                    if self.at(*i, *j) == *id {
-                        *self.at_ref(*i, *j) = 0;
-                    }
-                    if self.at(i + 1, *j) == *id {
-                        *self.at_ref(i + 1, *j) = 0;
-                    }
-                    *self.at_ref(*i, j - 1) = *id;
-                    *self.at_ref(i + 1, j - 1) = *id;
+                if let Some(animation) = animation {
+                    animation.finish()?;
                 }
-                Direction::R => {
-                    if self.at(*i, *j) == *id {
-                        *self.at_ref(*i, *j) = 0;
-                    }
-                    if self.at(i + 1, *j) == *id {
-                        *self.at_ref(i + 1, *j) = 0;
-                    }
-                    *self.at_ref(*i, j + 1) = *id;
-                    *self.at_ref(i + 1, j + 1) = *id;
+            } else {
+                eprintln!("Generating...");
+                shuffling.grow(&mut x, steps)?;
+            }
+            match output {
+                Some(output) => write_diamond(&x, &output, StateFormat::from_path(&output)),
+                None => Ok(()),
+            }
+        }
+        Command::Render {
+            state,
+            style,
+            seed,
+            pips,
+            embed,
+            pips_invert,
+        } => {
+            let x = State::load(&state)?;
+            let colors = style.colors(seed.unwrap_or_else(random));
+            eprintln!("Rendering...");
+            match (pips, embed) {
+                (Some(csv), Some(embed)) => {
+                    let Some((rows, cols, tiles)) = x.dominoes() else {
+                        return Err(TilingError::Region(
+                            "double-six layouts need a domino tiling".to_string(),
+                        ));
+                    };
+                    let image = EmbeddableImage::FileName(embed).load()?;
+                    let pips = DoubleSix::new(rows, cols, tiles.into_iter(), &image, pips_invert);
+                    save_pips(&pips, &csv)?;
+                    style.draw(&pips, &colors)?;
                 }
+                _ => style.draw(x.tiling(), &colors)?,
             }
-        });
-    }
-    fn tile(&mut self, embed: &Option<EmbeddableImage>) {
-        let im = if let Some(ef) = embed {
-            let img = match ef {
-                EmbeddableImage::FileName(fname) => {
-                    image::open(fname).unwrap_or_else(|_| panic!("NO IMAGE {}!", &fname))
+            eprintln!("Done.");
+            Ok(())
+        }
+        Command::Stats {
+            state,
+            steps,
+            seed,
+            shuffling,
+            height_csv,
+            height_png,
+            height_raw,
+            probabilities_csv,
+            probabilities_png,
+            samples,
+            style,
+        } => {
+            let seed = seed.unwrap_or_else(random);
+            let colors = style.colors(seed);
+            let steps = steps.unwrap_or(if state.is_some() { 0 } else { 256 });
+            if let Some(samples) = samples {
+                eprintln!("Sampling...");
+                let progress = Mutex::new(progress_bar(samples));
+                let ensemble = Ensemble::sample(
+                    samples,
+                    steps,
+                    seed,
+                    |seed| shuffling.diamond(None, steps, seed),
+                    shuffling.embed.clone().map(EmbeddableImage::FileName),
+                    |done| {
+                        if let Ok(mut progress) = progress.lock() {
+                            progress(done);
+                        }
+                    },
+                )?;
+                eprintln!("Rendering...");
+                let densities = ensemble.densities();
+                densities.save_csv(&style.filename)?;
+                densities.save_heatmaps(&style.filename, style.tile_size, &colors)?;
+                let summary = ensemble.summary();
+                print!("{summary}");
+                let path = format!("{}_stats.csv", style.filename);
+                std::fs::write(&path, summary)
+                    .map_err(|source| TilingError::Io { path, source })?;
+                eprintln!("Done.");
+                return Ok(());
+            }
+            let x = shuffling.diamond(state.as_deref(), steps, seed)?;
+            if probabilities_csv.is_some() || probabilities_png.is_some() {
+                eprintln!("Computing...");
+                if shuffling.probability_expr.is_some() {
+                    eprintln!(
+                        "warning: placement probabilities are only approximate with an expression"
+                    );
                 }
-                EmbeddableImage::FileBytes(data) => {
-                    image::load_from_memory(data).unwrap_or_else(|_| panic!("NO IMAGE IN REQUEST!"))
+                let marginals = x.edge_probabilities(x.order() + steps)?;
+                if let Some(prefix) = probabilities_csv {
+                    marginals.save_csv(&prefix)?;
                 }
-            };
-            let img = img.grayscale().resize_exact(
-                self.size as u32,
-                self.size as u32,
-                FilterType::Nearest,
-            );
-            Some(img)
-        } else {
-            None
-        };
-        while let Some(c) = self.find_square() {
-            self.tile_square(c, &im)
-        }
-    }
-    pub fn step(&mut self, embed: &Option<EmbeddableImage>) {
-        self.eliminate_stuck_tiles();
-        self.extend();
-        self.move_tiles();
-        self.tile(embed);
-    }
-    pub fn generate(&mut self, n: usize, embed: Option<EmbeddableImage>) {
-        let mut progress_bar = MappingBar::with_range(0, n);
-        (0..n).for_each(|i| {
-            progress_bar.set(i + 1);
-            if progress_bar.has_progressed_significantly() {
-                print!("\r{progress_bar}");
+                if let Some(prefix) = probabilities_png {
+                    marginals.save_heatmaps(&prefix, style.tile_size, &colors)?;
+                }
+                eprintln!("Done.");
             }
-            if i == n - 1 {
-                self.step(&embed);
-            } else {
-                self.step(&None);
+            [
+                (height_csv, HeightFormat::Csv),
+                (height_png, HeightFormat::Png),
+                (height_raw, HeightFormat::Raw),
+            ]
+            .into_iter()
+            .filter_map(|(path, format)| path.map(|path| (path, format)))
+            .try_for_each(|(path, format)| x.save_height_function(format, &path))?;
+            if state.is_some() {
+                print!("{}", report(&x));
             }
-        });
-        println!();
-    }
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        (0..self.size).for_each(|i| {
-            (0..self.size).for_each(|j| {
-                if self.at(i, j) > 0 {
-                    print!(
-                        " {} ",
-                        match self.tiles[&(self.at(i, j))].dir {
-                            Direction::T => {
-                                "T"
-                            }
-                            Direction::B => {
-                                "B"
-                            }
-                            Direction::L => {
-                                "L"
-                            }
-                            Direction::R => {
-                                "R"
-                            }
-                        }
+            Ok(())
+        }
+        Command::Convert { input, output, to } => {
+            let x = Diamond::load(&input)?;
+            write_diamond(
+                &x,
+                &output,
+                to.unwrap_or_else(|| StateFormat::from_path(&output)),
+            )
+        }
+        Command::Serve {
+            address,
+            port,
+            workers,
+            max_steps,
+            max_side,
+            max_weight_period,
+            max_tile_size,
+            max_upload,
+        } => web::serve(
+            &address,
+            port,
+            workers,
+            web::Limits {
+                max_steps,
+                max_side,
+                max_weight_period,
+                max_tile_size,
+                max_upload,
+            },
+        )
+        .map_err(|source| TilingError::Io {
+            path: format!("{address}:{port}"),
+            source,
+        }),
+        Command::Glauber {
+            sweeps,
+            bias,
+            extremal,
+            trace,
+            steps,
+            input,
+            seed,
+            shuffling,
+            output,
+            style,
+        } => {
+            let seed = seed.unwrap_or_else(random);
+            let colors = style.colors(seed);
+            let mut x = shuffling.diamond(input.as_deref(), steps, seed)?;
+            let order = if input.is_some() { x.order() } else { steps };
+            match extremal {
+                Some(extremal) => x.set_extremal(order, extremal == Extremal::Horizontal),
+                None if input.is_none() => {
+                    eprintln!("Generating...");
+                    x.reserve(steps * 2);
+                    shuffling.grow(&mut x, steps)?;
+                }
+                None => {}
+            }
+            eprintln!("Running...");
+            let mut log = "sweep,flips,horizontal,center_height\n".to_string();
+            for i in 0..sweeps {
+                let flips = x.glauber(1, bias);
+                if trace.is_some() {
+                    let tiles = x.tiles().count().max(1);
+                    let horizontal = x
+                        .tiles()
+                        .filter(|t| matches!(t.dir, Direction::T | Direction::B))
+                        .count();
+                    let n = x.size() + 1;
+                    let center = x.height_function()[n * n / 2].unwrap_or_default();
+                    log += &format!(
+                        "{},{flips},{},{center}\n",
+                        i + 1,
+                        horizontal as f64 / tiles as f64
                     );
-                } else {
-                    print!("   ");
                 }
-            });
-            println!();
-        });
-        println!();
-    }
-    #[allow(dead_code)]
-    pub fn print_debug(&self) {
-        (0..self.size).for_each(|i| {
-            (0..self.size).for_each(|j| print!("{:4} ", self.at(i, j)));
-            println!();
-        });
-        println!();
-    }
-    pub fn draw_image(&self, ts: usize, colors: &Colors, action: ImageAction) -> Option<Vec<u8>> {
-        let tile_size = if ts > 16 { ts / 2 } else { ts };
-        let mut im = RgbaImage::new(
-            (self.size * tile_size) as u32,
-            (self.size * tile_size) as u32,
-        );
-        draw_filled_rect_mut(
-            &mut im,
-            Rect::at(0, 0).of_size(
-                (self.size * tile_size) as u32,
-                (self.size * tile_size) as u32,
-            ),
-            Rgba([128, 128, 128, 255]),
-        );
-        let mut progress_bar = MappingBar::with_range(0, self.tiles.len());
-        self.tiles.values().enumerate().for_each(|(counter, tile)| {
-            let (i, j) = tile.pos;
-            let (src, w, h) = match tile.dir {
-                Direction::T => (colors.top, 2, 1),
-                Direction::B => (colors.bottom, 2, 1),
-                Direction::L => (colors.left, 1, 2),
-                Direction::R => (colors.right, 1, 2),
-            };
-            draw_hollow_rect_mut(
-                &mut im,
-                Rect::at((j * tile_size) as i32, (i * tile_size) as i32)
-                    .of_size((w * tile_size) as u32, (h * tile_size) as u32),
-                colors.grid,
-            );
-            draw_filled_rect_mut(
-                &mut im,
-                Rect::at((j * tile_size) as i32 + 1, (i * tile_size) as i32 + 1)
-                    .of_size((w * tile_size) as u32 - 2, (h * tile_size) as u32 - 2),
-                src,
-            );
-            progress_bar.set(counter + 1);
-            if progress_bar.has_progressed_significantly() {
-                print!("\r{progress_bar}");
             }
-        });
-        println!();
-        if ts > 16 {
-            im = resize(&im, im.width() * 2, im.height() * 2, FilterType::Nearest);
+            if let Some(path) = trace {
+                std::fs::write(&path, log).map_err(|source| TilingError::Io { path, source })?;
+            }
+            eprintln!("Rendering...");
+            style.draw(&x, &colors)?;
+            eprintln!("Done.");
+            match output {
+                Some(output) => write_diamond(&x, &output, StateFormat::from_path(&output)),
+                None => Ok(()),
+            }
         }
-        match action {
-            ImageAction::Save(s) => {
-                im.save(s).expect("FAILED TO SAVE AN IMAGE!");
-                None
+        Command::Art {
+            target,
+            shape,
+            width,
+            height,
+            iterations,
+            start_temperature,
+            end_temperature,
+            log,
+            seed,
+            pips,
+            pips_invert,
+            output,
+            style,
+        } => {
+            let seed = seed.unwrap_or_else(random);
+            let colors = style.colors(seed);
+            let image = EmbeddableImage::FileName(target).load_rgb()?;
+            let mut region = match shape {
+                Shape::Rectangle => Region::rectangle(height, width, seed)?,
+                Shape::Diamond => Region::aztec(width / 2, seed)?,
+            };
+            eprintln!("Annealing...");
+            let errors = region.anneal(
+                &image,
+                &colors,
+                &Annealing {
+                    iterations,
+                    start_temperature,
+                    end_temperature,
+                },
+            );
+            let mut csv = "iteration,error\n".to_string();
+            errors.iter().enumerate().for_each(|(i, error)| {
+                eprintln!("Iteration {i}: error {error:.6}");
+                csv += &format!("{i},{error}\n");
+            });
+            if let Some(path) = log {
+                std::fs::write(&path, csv).map_err(|source| TilingError::Io { path, source })?;
             }
-            ImageAction::Return => {
-                let mut bytes: Vec<u8> = Vec::new();
-                match DynamicImage::ImageRgba8(im)
-                    .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
-                {
-                    Ok(()) => Some(bytes),
-                    Err(_) => None,
+            eprintln!("Rendering...");
+            match pips {
+                Some(csv) => {
+                    let image = image::DynamicImage::ImageRgb8(image).to_luma8();
+                    let pips = DoubleSix::new(
+                        region.rows(),
+                        region.cols(),
+                        region.tiles(),
+                        &image,
+                        pips_invert,
+                    );
+                    save_pips(&pips, &csv)?;
+                    style.draw(&pips, &colors)?;
                 }
+                None => style.draw(&region, &colors)?,
             }
+            eprintln!("Done.");
+            match output {
+                Some(output) => write_state(&region, &output),
+                None => Ok(()),
+            }
+        }
+        Command::Verify { state } => {
+            let x = Diamond::load(&state)?;
+            println!(
+                "{state}: consistent order {} diamond with {} dominoes",
+                x.order(),
+                x.tiles().count()
+            );
+            Ok(())
         }
     }
 }
 
-fn parse_hex(input: &str) -> Result<u32, ParseIntError> {
-    u32::from_str_radix(input, 16)
+/// Draws a progress bar on standard error for `total` units of work, to be
+/// called with the number of units done.
+fn progress_bar(total: usize) -> impl FnMut(usize) {
+    let mut bar = MappingBar::with_range(0, total);
+    move |done| {
+        bar.set(done);
+        if bar.has_progressed_significantly() {
+            eprint!("\r{bar}");
+        }
+        if done == total {
+            eprintln!();
+        }
+    }
 }
 
-struct Colors {
-    top: Rgba<u8>,
-    bottom: Rgba<u8>,
-    left: Rgba<u8>,
-    right: Rgba<u8>,
-    grid: Rgba<u8>,
+/// A saved tiling of any kind.
+enum State {
+    Diamond(Diamond),
+    Region(Region),
+    Hexagon(Hexagon),
 }
 
-impl Colors {
-    pub fn new(t: u32, b: u32, l: u32, r: u32, g: u32) -> Colors {
-        Colors {
-            top: Colors::int_to_color(t),
-            bottom: Colors::int_to_color(b),
-            left: Colors::int_to_color(l),
-            right: Colors::int_to_color(r),
-            grid: Colors::int_to_color(g),
+impl State {
+    /// Reads `path` as a diamond, then as a region and then as a hexagon,
+    /// reporting why it is not a diamond if it parses as none of them and
+    /// what is wrong with it if it parses but is inconsistent.
+    fn load(path: &str) -> Result<State, TilingError> {
+        let err = match Diamond::load(path) {
+            Ok(x) => return Ok(State::Diamond(x)),
+            Err(err @ TilingError::State(_)) => err,
+            Err(err) => return Err(err),
+        };
+        match Region::load(path) {
+            Ok(x) => return Ok(State::Region(x)),
+            Err(TilingError::State(_)) => {}
+            Err(err) => return Err(err),
+        }
+        match Hexagon::load(path) {
+            Ok(x) => Ok(State::Hexagon(x)),
+            Err(TilingError::State(_)) => Err(err),
+            Err(err) => Err(err),
         }
     }
-    pub fn default() -> Colors {
-        Colors {
-            top: Rgba([255, 0, 0, 255]),
-            bottom: Rgba([0, 0, 255, 255]),
-            left: Rgba([255, 255, 0, 255]),
-            right: Rgba([0, 255, 0, 255]),
-            grid: Rgba([0, 0, 0, 255]),
+    fn tiling(&self) -> &dyn Tiling {
+        match self {
+            State::Diamond(x) => x,
+            State::Region(x) => x,
+            State::Hexagon(x) => x,
         }
     }
-    fn int_to_color(c: u32) -> Rgba<u8> {
-        Rgba([
-            ((c >> 24) & 0xff) as u8,
-            ((c >> 16) & 0xff) as u8,
-            ((c >> 8) & 0xff) as u8,
-            (c & 0xff) as u8,
-        ])
+    /// Grid size and dominoes of a domino tiling.
+    fn dominoes(&self) -> Option<(usize, usize, Vec<Tile>)> {
+        match self {
+            State::Diamond(x) => Some((x.size(), x.size(), x.tiles().collect())),
+            State::Region(x) => Some((x.rows(), x.cols(), x.tiles().collect())),
+            State::Hexagon(_) => None,
+        }
     }
 }
 
-#[derive(Parser)]
-#[command(version = "1.0", author = "Abbath")]
-struct Opts {
-    #[arg(short('n'), long, default_value = "256")]
-    steps: usize,
-    #[arg(short, long, default_value = "test.png")]
-    filename: String,
-    #[arg(short('s'), long, default_value = "8")]
-    tile_size: usize,
-    #[arg(short, long, default_value = "ff0000ff", value_parser = parse_hex)]
-    top_color: u32,
-    #[arg(short, long, default_value = "0000ffff", value_parser = parse_hex)]
-    bottom_color: u32,
-    #[arg(short, long, default_value = "ffff00ff", value_parser = parse_hex)]
-    left_color: u32,
-    #[arg(short, long, default_value = "00ff00ff", value_parser = parse_hex)]
-    right_color: u32,
-    #[arg(short, long, default_value = "000000ff", value_parser = parse_hex)]
-    grid_color: u32,
-    #[arg(short('c'), long)]
-    random_colors: bool,
-    #[arg(short('a'), long)]
-    save_all_steps: bool,
-    #[arg(short('w'), long)]
-    web: bool,
-    #[arg(short('i'), long)]
-    input: Option<String>,
-    #[arg(short('o'), long)]
-    output: Option<String>,
-    #[arg(short('p'), long, default_value = "0.5")]
-    probability: f64,
-    #[arg(short('e'), long)]
-    embed: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct Params {
-    fname: Option<File>,
-    steps: usize,
-    size: usize,
-    p: usize,
-}
-
-#[post("/")]
-async fn index_post(params: Multipart<Params>) -> HttpResponse {
-    let mut x = Diamond::new(params.p as f64 / 100.0f64, params.steps * 2);
-    x.generate(
-        params.steps,
-        params
-            .fname
-            .as_ref()
-            .map(|f| EmbeddableImage::FileBytes(f.data().to_vec())),
+/// Counts of each direction and the height at the center of a diamond as
+/// CSV.
+fn report(x: &Diamond) -> String {
+    let mut counts = [0usize; 4];
+    x.tiles().for_each(|t| counts[t.dir as usize - 1] += 1);
+    let total = counts.iter().sum::<usize>().max(1) as f64;
+    let n = x.size() + 1;
+    let center = x.height_function()[n * n / 2].unwrap_or_default();
+    let mut csv = format!(
+        "statistic,value\norder,{}\ndominoes,{}\n",
+        x.order(),
+        counts.iter().sum::<usize>()
     );
-    let f = x
-        .draw_image(params.size, &Colors::default(), ImageAction::Return)
-        .expect("IMAGE IS NOT HERE!");
-    HttpResponse::Ok()
-        .append_header(header::ContentDisposition::attachment("image.png"))
-        .content_type("image/png")
-        .body(f)
-}
-
-#[get("/")]
-async fn index_get() -> HttpResponse {
-    let html = r#"<!DOCTYPE html>
-    <html>
-    <body>
-
-    <h2>Tilings</h2>
-
-    <form method="post" action="/" enctype="multipart/form-data">
-      <label for="fname">Image:</label><br>
-      <input type="file" id="fname" name="fname" accept="image/png, image/jpeg"><br>
-      <label for="lname">Steps:</label><br>
-      <input type="number" id="steps" name="steps" value="256"><br>
-      <label for="lname">Size:</label><br>
-      <input type="number" id="size" name="size" value="4"><br>
-      <label for="lname">Probability (%):</label><br>
-      <input type="number" id="p" name="p" value="50" min="0" max="100"><br><br>
-      <input type="submit" value="Submit">
-    </form>
-
-    </body>
-    </html>"#;
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(html)
-}
-
-#[get("/{steps}/{size}")]
-async fn index(path: web::Path<(usize, usize)>) -> HttpResponse {
-    let (steps, size) = path.into_inner();
-    let mut x = Diamond::new(0.5, steps * 2);
-    x.generate(steps, None);
-    let f = x
-        .draw_image(size, &Colors::default(), ImageAction::Return)
-        .expect("IMAGE IS NOT HERE!");
-    HttpResponse::Ok().content_type("image/png").body(f)
+    ["T", "B", "L", "R"]
+        .iter()
+        .zip(counts)
+        .for_each(|(name, count)| {
+            csv += &format!("{name},{count}\n");
+        });
+    csv += &format!(
+        "horizontal_fraction,{}\ncenter_height,{center}\n",
+        (counts[0] + counts[1]) as f64 / total
+    );
+    csv
 }
 
-#[actix_web::main]
-async fn amain() -> std::io::Result<()> {
-    let port = 3000;
-    HttpServer::new(|| {
-        App::new()
-            .service(index)
-            .service(index_get)
-            .service(index_post)
-    })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+/// Writes the placements of a double-six layout and reports how many sets it
+/// takes.
+fn save_pips(pips: &DoubleSix, csv: &str) -> Result<(), TilingError> {
+    pips.save_csv(csv)?;
+    let sets = pips.sets();
+    eprintln!(
+        "{sets} complete double-six sets, {} dominoes left over",
+        sets * 28 - pips.placements().len()
+    );
+    Ok(())
 }
 
-fn random_color() -> u32 {
-    let r: u8 = random();
-    let g: u8 = random();
-    let b: u8 = random();
-    let a: u8 = 255;
-    ((r as u32) << 24) | ((g as u32) << 16) | ((b as u32) << 8) | a as u32
+/// Writes the state as JSON to `output`, or to standard output if it is
+/// `-`.
+fn write_state(x: &dyn Tiling, output: &str) -> Result<(), TilingError> {
+    match output {
+        "-" => println!("{}", x.to_json()?),
+        output => x.save(output)?,
+    }
+    Ok(())
 }
 
-fn main() {
-    let opts: Opts = Opts::parse();
-    if opts.web {
-        amain().unwrap_or_else(|s| panic!("SOMETHING WENT WRONG {s}!"));
-        return;
-    }
-    let mut x = match opts.input {
-        Some(input) => {
-            let content = std::fs::read_to_string(&input)
-                .unwrap_or_else(|err| panic!("COULD NOT LOAD FILE {input} WITH ERROR {err}!"));
-            serde_json::from_str(&content)
-                .unwrap_or_else(|err| panic!("COULD NOT PARSE FILE {input} WITH ERROR {err}!"))
-        }
-        None => Diamond::new(opts.probability, opts.steps * 2),
-    };
-    let colors: Colors = if opts.random_colors {
-        Colors::new(
-            random_color(),
-            random_color(),
-            random_color(),
-            random_color(),
-            random_color(),
-        )
-    } else {
-        Colors::new(
-            opts.top_color,
-            opts.bottom_color,
-            opts.left_color,
-            opts.right_color,
-            opts.grid_color,
-        )
-    };
-    if opts.save_all_steps {
-        for i in 0..opts.steps {
-            println!("Step {i}");
-            x.step(&None);
-            println!("Saving image {i}");
-            x.draw_image(
-                opts.tile_size,
-                &colors,
-                ImageAction::Save(format!(
-                    "{}_{:0width$}.png",
-                    opts.filename,
-                    i + 1,
-                    width = opts.steps.ilog10() as usize + 1
-                )),
-            );
-        }
-    } else {
-        println!("Generating...");
-        x.generate(opts.steps, opts.embed.map(EmbeddableImage::FileName));
-        println!("Rendering...");
-        x.draw_image(opts.tile_size, &colors, ImageAction::Save(opts.filename));
-        println!("Done.");
-    }
-    if let Some(output) = opts.output {
-        let serialized = serde_json::to_string(&x).unwrap_or("{}".to_string());
-        if output.is_empty() {
-            println!("{serialized}");
-        } else {
-            std::fs::write(&output, serialized)
-                .unwrap_or_else(|err| panic!("COULD NOT SAVE FILE {output} {err}!"));
+/// Writes a diamond's state to `output` in `format`, on standard output if
+/// it is `-`.
+fn write_diamond(x: &Diamond, output: &str, format: StateFormat) -> Result<(), TilingError> {
+    match (format, output) {
+        (StateFormat::Binary, "-") => {
+            use std::io::Write;
+            std::io::stdout()
+                .write_all(&x.to_binary()?)
+                .map_err(|source| TilingError::Io {
+                    path: output.to_string(),
+                    source,
+                })
         }
+        (StateFormat::Binary, _) => x.save_binary(output),
+        (StateFormat::Json, _) => write_state(x, output),
     }
 }
@@ -0,0 +1,428 @@
+use crate::diamond::{Coords, Direction, Tile};
+use crate::error::TilingError;
+use crate::render::{Colors, cell_pixels};
+use crate::tiling::Tiling;
+use image::imageops::{FilterType, resize};
+use image::{GrayImage, Rgba, RgbaImage};
+use imageproc::drawing::{
+    draw_filled_circle_mut, draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut,
+};
+use imageproc::rect::Rect;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Pip positions on a half of a domino for each count, as fractions of the
+/// half's side.
+const LAYOUTS: [&[(f32, f32)]; 7] = [
+    &[],
+    &[(0.5, 0.5)],
+    &[(0.25, 0.25), (0.75, 0.75)],
+    &[(0.25, 0.25), (0.5, 0.5), (0.75, 0.75)],
+    &[(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)],
+    &[
+        (0.25, 0.25),
+        (0.75, 0.25),
+        (0.5, 0.5),
+        (0.25, 0.75),
+        (0.75, 0.75),
+    ],
+    &[
+        (0.25, 0.25),
+        (0.75, 0.25),
+        (0.25, 0.5),
+        (0.75, 0.5),
+        (0.25, 0.75),
+        (0.75, 0.75),
+    ],
+];
+
+/// One physical domino of a [`DoubleSix`] layout.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Placement {
+    /// Top-left cell covered by the domino.
+    pub pos: Coords,
+    /// Whether the domino lies horizontally.
+    pub horizontal: bool,
+    /// Pips on the top or left half, then on the bottom or right half.
+    pub pips: (u8, u8),
+}
+
+/// A domino tiling laid out with the faces of complete double-six sets, each
+/// half showing about as many pips as its brightness asks for.
+///
+/// Dominoes are black with white pips, so brighter cells get more pips, or
+/// white with black pips when inverted.
+#[derive(Clone, Serialize)]
+pub struct DoubleSix {
+    rows: usize,
+    cols: usize,
+    invert: bool,
+    sets: usize,
+    placements: Vec<Placement>,
+}
+
+/// Residual graph of a min-cost flow problem.
+struct Network {
+    /// Target, remaining capacity and cost of every edge, each followed by
+    /// its reverse.
+    edges: Vec<(usize, usize, i64)>,
+    adjacent: Vec<Vec<usize>>,
+}
+
+impl Network {
+    fn new(nodes: usize) -> Network {
+        Network {
+            edges: Vec::new(),
+            adjacent: vec![Vec::new(); nodes],
+        }
+    }
+    fn add_edge(&mut self, from: usize, to: usize, capacity: usize, cost: i64) -> usize {
+        self.adjacent[from].push(self.edges.len());
+        self.edges.push((to, capacity, cost));
+        self.adjacent[to].push(self.edges.len());
+        self.edges.push((from, 0, -cost));
+        self.edges.len() - 2
+    }
+    /// Pushes as much flow as possible from `source` to `sink`, always along
+    /// the cheapest remaining path, which gives the cheapest maximum flow.
+    fn flow(&mut self, source: usize, sink: usize) {
+        let nodes = self.adjacent.len();
+        loop {
+            // Shortest paths by Bellman-Ford with a queue, as reverse edges
+            // cost less than nothing.
+            let mut distance = vec![i64::MAX; nodes];
+            let mut via = vec![usize::MAX; nodes];
+            let mut queued = vec![false; nodes];
+            let mut queue = std::collections::VecDeque::from([source]);
+            distance[source] = 0;
+            while let Some(u) = queue.pop_front() {
+                queued[u] = false;
+                self.adjacent[u].iter().for_each(|&e| {
+                    let (v, capacity, cost) = self.edges[e];
+                    if capacity > 0 && distance[u] + cost < distance[v] {
+                        distance[v] = distance[u] + cost;
+                        via[v] = e;
+                        if !queued[v] {
+                            queued[v] = true;
+                            queue.push_back(v);
+                        }
+                    }
+                });
+            }
+            if distance[sink] == i64::MAX {
+                return;
+            }
+            let mut path = Vec::new();
+            let mut v = sink;
+            while v != source {
+                path.push(via[v]);
+                v = self.edges[via[v] ^ 1].0;
+            }
+            let amount = path.iter().map(|&e| self.edges[e].1).min().unwrap_or(0);
+            path.iter().for_each(|&e| {
+                self.edges[e].1 -= amount;
+                self.edges[e ^ 1].1 += amount;
+            });
+        }
+    }
+}
+
+/// Chooses a face for every domino whose halves ask for `wanted` pips,
+/// taking each of the 28 faces at most `sets` times and turning it around
+/// where that fits better, so that the squared differences in pips add up
+/// to as little as possible.
+fn assign(wanted: &[(u8, u8)], sets: usize) -> Vec<(u8, u8)> {
+    let faces: Vec<(u8, u8)> = (0..7).flat_map(|a| (a..7).map(move |b| (a, b))).collect();
+    let fit = |(x, y): (u8, u8), (a, b): (u8, u8)| {
+        let cost = |p: u8, q: u8| (p as i64 - x as i64).pow(2) + (q as i64 - y as i64).pow(2);
+        if cost(a, b) <= cost(b, a) {
+            (cost(a, b), (a, b))
+        } else {
+            (cost(b, a), (b, a))
+        }
+    };
+    // Dominoes asking for the same pips are interchangeable, so the flow
+    // goes from the 49 requests through the 28 faces.
+    let mut requested = [0usize; 49];
+    wanted
+        .iter()
+        .for_each(|&(x, y)| requested[x as usize * 7 + y as usize] += 1);
+    let (source, sink) = (49 + faces.len(), 49 + faces.len() + 1);
+    let mut network = Network::new(sink + 1);
+    let mut routes = Vec::new();
+    requested.iter().enumerate().for_each(|(r, &count)| {
+        if count > 0 {
+            network.add_edge(source, r, count, 0);
+            faces.iter().enumerate().for_each(|(f, &face)| {
+                let (cost, _) = fit(((r / 7) as u8, (r % 7) as u8), face);
+                routes.push((r, f, network.add_edge(r, 49 + f, count, cost)));
+            });
+        }
+    });
+    (0..faces.len()).for_each(|f| {
+        network.add_edge(49 + f, sink, sets, 0);
+    });
+    network.flow(source, sink);
+    let mut supply: Vec<Vec<(usize, usize)>> = vec![Vec::new(); 49];
+    routes.iter().for_each(|&(r, f, e)| {
+        let used = network.edges[e ^ 1].1;
+        if used > 0 {
+            supply[r].push((f, used));
+        }
+    });
+    wanted
+        .iter()
+        .map(|&(x, y)| {
+            let faces_left = &mut supply[x as usize * 7 + y as usize];
+            let Some((f, left)) = faces_left.last_mut() else {
+                return (x, y);
+            };
+            let face = faces[*f];
+            *left -= 1;
+            if *left == 0 {
+                faces_left.pop();
+            }
+            fit((x, y), face).1
+        })
+        .collect()
+}
+
+impl DoubleSix {
+    /// Chooses the faces for `tiles` covering a `rows x cols` grid from the
+    /// fewest double-six sets that hold a domino for each tile, matching
+    /// `image` resized to one pixel per cell as closely as the sets allow.
+    pub fn new(
+        rows: usize,
+        cols: usize,
+        tiles: impl Iterator<Item = Tile>,
+        image: &GrayImage,
+        invert: bool,
+    ) -> DoubleSix {
+        let image = resize(image, cols as u32, rows as u32, FilterType::Triangle);
+        let pips = |(i, j): Coords| {
+            let brightness = image.get_pixel(j as u32, i as u32).0[0] as f64 / 255.0;
+            let pips = (brightness * 6.0).round() as u8;
+            if invert { 6 - pips } else { pips }
+        };
+        let tiles: Vec<(Coords, bool)> = tiles
+            .map(|tile| (tile.pos, matches!(tile.dir, Direction::T | Direction::B)))
+            .collect();
+        let wanted: Vec<(u8, u8)> = tiles
+            .iter()
+            .map(|&((i, j), horizontal)| {
+                let other = if horizontal { (i, j + 1) } else { (i + 1, j) };
+                (pips((i, j)), pips(other))
+            })
+            .collect();
+        let sets = tiles.len().div_ceil(28);
+        let placements = tiles
+            .iter()
+            .zip(assign(&wanted, sets))
+            .map(|(&(pos, horizontal), pips)| Placement {
+                pos,
+                horizontal,
+                pips,
+            })
+            .collect();
+        DoubleSix {
+            rows,
+            cols,
+            invert,
+            sets,
+            placements,
+        }
+    }
+    /// Every domino with its face.
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+    /// How many dominoes of each face are used, indexed by the smaller and
+    /// the larger pip count.
+    pub fn counts(&self) -> [[usize; 7]; 7] {
+        let mut counts = [[0; 7]; 7];
+        self.placements.iter().for_each(|p| {
+            let (a, b) = p.pips;
+            counts[a.min(b) as usize][a.max(b) as usize] += 1;
+        });
+        counts
+    }
+    /// Number of complete double-six sets the faces are taken from.
+    pub fn sets(&self) -> usize {
+        self.sets
+    }
+    /// The placements as CSV with one row per domino.
+    pub fn to_csv(&self) -> String {
+        let mut csv = "row,column,orientation,first,second\n".to_string();
+        self.placements.iter().for_each(|p| {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{}",
+                p.pos.0,
+                p.pos.1,
+                if p.horizontal {
+                    "horizontal"
+                } else {
+                    "vertical"
+                },
+                p.pips.0,
+                p.pips.1
+            );
+        });
+        csv
+    }
+    /// Writes the placements as CSV to `path`.
+    pub fn save_csv(&self, path: &str) -> Result<(), TilingError> {
+        std::fs::write(path, self.to_csv()).map_err(|err| TilingError::io(path, err))
+    }
+
+    fn palette(&self) -> (Rgba<u8>, Rgba<u8>) {
+        let (black, white) = (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+        if self.invert {
+            (white, black)
+        } else {
+            (black, white)
+        }
+    }
+    /// Pip centers of a domino in cells, with the domino's top-left corner
+    /// at the origin.
+    fn pip_centers(p: &Placement) -> impl Iterator<Item = (f32, f32)> {
+        let second = if p.horizontal { (1.0, 0.0) } else { (0.0, 1.0) };
+        LAYOUTS[p.pips.0 as usize].iter().copied().chain(
+            LAYOUTS[p.pips.1 as usize]
+                .iter()
+                .map(move |(x, y)| (x + second.0, y + second.1)),
+        )
+    }
+}
+
+impl Tiling for DoubleSix {
+    /// Rasterizes the dominoes with `ts` pixels per cell, pips included.
+    fn render(&self, ts: usize, colors: &Colors) -> RgbaImage {
+        let px = cell_pixels(ts);
+        let (body, pip) = self.palette();
+        let mut im = RgbaImage::from_pixel(
+            (self.cols * px) as u32,
+            (self.rows * px) as u32,
+            Rgba([128, 128, 128, 255]),
+        );
+        let radius = (px / 10).max(1) as i32;
+        self.placements.iter().for_each(|p| {
+            let (w, h) = if p.horizontal { (2, 1) } else { (1, 2) };
+            let (x, y) = ((p.pos.1 * px) as i32, (p.pos.0 * px) as i32);
+            if px > 0 {
+                draw_hollow_rect_mut(
+                    &mut im,
+                    Rect::at(x, y).of_size((w * px) as u32, (h * px) as u32),
+                    colors.grid,
+                );
+            }
+            if px > 2 {
+                draw_filled_rect_mut(
+                    &mut im,
+                    Rect::at(x + 1, y + 1).of_size((w * px - 2) as u32, (h * px - 2) as u32),
+                    body,
+                );
+            }
+            let middle = px as f32;
+            let divider = if p.horizontal {
+                ((middle, 2.0), (middle, middle - 3.0))
+            } else {
+                ((2.0, middle), (middle - 3.0, middle))
+            };
+            draw_line_segment_mut(
+                &mut im,
+                (x as f32 + divider.0.0, y as f32 + divider.0.1),
+                (x as f32 + divider.1.0, y as f32 + divider.1.1),
+                pip,
+            );
+            DoubleSix::pip_centers(p).for_each(|(cx, cy)| {
+                draw_filled_circle_mut(
+                    &mut im,
+                    (
+                        x + (cx * px as f32).round() as i32,
+                        y + (cy * px as f32).round() as i32,
+                    ),
+                    radius,
+                    pip,
+                );
+            });
+        });
+        im
+    }
+    /// Draws the dominoes as an SVG document with `ts` units per cell.
+    fn svg(&self, ts: usize, colors: &Colors) -> String {
+        let (width, height) = (self.cols * ts, self.rows * ts);
+        let (body, pip) = self.palette();
+        let (body, pip, grid) = (
+            Colors::to_svg(body),
+            Colors::to_svg(pip),
+            Colors::to_svg(colors.grid),
+        );
+        let radius = ts as f32 / 10.0;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\">\n\
+             <rect width=\"{width}\" height=\"{height}\" fill=\"#808080\"/>\n"
+        );
+        self.placements.iter().for_each(|p| {
+            let (w, h) = if p.horizontal { (2, 1) } else { (1, 2) };
+            let (x, y) = (p.pos.1 * ts, p.pos.0 * ts);
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{}.5\" y=\"{}.5\" width=\"{}\" height=\"{}\" rx=\"{radius}\" \
+                 fill=\"{body}\" stroke=\"{grid}\"/>",
+                x,
+                y,
+                (w * ts).saturating_sub(1),
+                (h * ts).saturating_sub(1)
+            );
+            // Both dividers end at the corner shared by the two halves.
+            let (x1, y1) = if p.horizontal {
+                (x + ts, y)
+            } else {
+                (x, y + ts)
+            };
+            let (x2, y2) = (x + ts, y + ts);
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{x1}\" y1=\"{y1}\" x2=\"{x2}\" y2=\"{y2}\" stroke=\"{pip}\"/>"
+            );
+            DoubleSix::pip_centers(p).for_each(|(cx, cy)| {
+                let _ = writeln!(
+                    svg,
+                    "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{radius:.2}\" fill=\"{pip}\"/>",
+                    x as f32 + cx * ts as f32,
+                    y as f32 + cy * ts as f32
+                );
+            });
+        });
+        svg.push_str("</svg>\n");
+        svg
+    }
+    /// Serializes the grid size and the placements.
+    fn to_json(&self) -> Result<String, TilingError> {
+        serde_json::to_string(self).map_err(TilingError::State)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_come_from_complete_sets() {
+        let faces = assign(&[(6, 6); 56], 2);
+        let mut counts = [[0; 7]; 7];
+        faces
+            .iter()
+            .for_each(|&(a, b)| counts[a.min(b) as usize][a.max(b) as usize] += 1);
+        assert!((0..7).all(|a| (a..7).all(|b| counts[a][b] == 2)));
+    }
+
+    #[test]
+    fn faces_turn_to_match() {
+        // One set with every face asked for larger half first.
+        let wanted: Vec<(u8, u8)> = (0..7).flat_map(|a| (a..7).map(move |b| (b, a))).collect();
+        assert_eq!(assign(&wanted, 1), wanted);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::diamond::{Diamond, Direction, EmbeddableImage};
+use crate::error::TilingError;
+use crate::render::Colors;
+use image::imageops::{FilterType, resize};
+use image::{Rgba, RgbaImage};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Probability of each domino placement, one `size x size` field per
+/// `Direction` indexed like `Tile::pos`.
+pub struct Marginals {
+    size: usize,
+    fields: [Vec<f64>; 4],
+}
+
+/// Placement frequencies and per-direction tile counts accumulated over
+/// independent samples without keeping the samples themselves.
+pub struct Ensemble {
+    samples: usize,
+    frequencies: Marginals,
+    counts: [f64; 4],
+    squares: [f64; 4],
+}
+
+impl Diamond {
+    /// Computes exact placement probabilities for the order `order` diamond
+    /// with Propp's edge-probability recursion. Each step mirrors `step`: a
+    /// domino lands in an odd block with the probability it had one order
+    /// earlier, and the block is filled by creation with the probability that
+    /// nothing landed in it. Order `k` is built by step `k`, as when
+    /// growing an empty diamond.
+    ///
+    /// The recursion relies on annihilated pairs balancing created ones,
+    /// which holds for a constant `p` and for weights. An expression that
+    /// varies from block to block or from step to step gives an
+    /// approximation, possibly a poor one.
+    ///
+    /// Fails if the diamond's weights were computed for a smaller order.
+    pub fn edge_probabilities(&self, order: usize) -> Result<Marginals, TilingError> {
+        self.check_order(order)?;
+        let shifts = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        let offsets: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 0), (0, 1)];
+        let mut prev = Marginals::new(0);
+        (1..=order).for_each(|k| {
+            let mut next = Marginals::new(2 * k);
+            (0..2 * k - 1).for_each(|i| {
+                (0..2 * k - 1)
+                    .filter(|j| (i + j + k) % 2 == 1 && Marginals::block_inside(2 * k, i, *j))
+                    .for_each(|j| {
+                        let landed: [f64; 4] = std::array::from_fn(|d| {
+                            let (di, dj) = shifts[d];
+                            let (oi, oj) = offsets[d];
+                            prev.get(d, (i + oi) as isize - di - 1, (j + oj) as isize - dj - 1)
+                        });
+                        let empty = 1.0 - landed.iter().sum::<f64>();
+                        let h = self.creation_probability(k, k, (i, j));
+                        let created = [h, h, 1.0 - h, 1.0 - h];
+                        (0..4).for_each(|d| {
+                            let (oi, oj) = offsets[d];
+                            next.fields[d][(i + oi) * 2 * k + j + oj] =
+                                landed[d] + empty * created[d];
+                        });
+                    });
+            });
+            prev = next;
+        });
+        Ok(prev)
+    }
+}
+
+impl Marginals {
+    fn new(size: usize) -> Marginals {
+        Marginals {
+            size,
+            fields: std::array::from_fn(|_| vec![0.0; size * size]),
+        }
+    }
+    fn block_inside(size: usize, i: usize, j: usize) -> bool {
+        let inside =
+            |i: usize, j: usize| (2 * i).abs_diff(size - 1) + (2 * j).abs_diff(size - 1) <= size;
+        inside(i, j) && inside(i + 1, j) && inside(i, j + 1) && inside(i + 1, j + 1)
+    }
+    /// Side length of the fields.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Row-major field of placement probabilities for `dir`.
+    pub fn field(&self, dir: Direction) -> &[f64] {
+        &self.fields[dir as usize - 1]
+    }
+    fn get(&self, d: usize, i: isize, j: isize) -> f64 {
+        if (0..self.size as isize).contains(&i) && (0..self.size as isize).contains(&j) {
+            self.fields[d][i as usize * self.size + j as usize]
+        } else {
+            0.0
+        }
+    }
+    /// Writes each field to `{prefix}_{T,B,L,R}.csv`.
+    pub fn save_csv(&self, prefix: &str) -> Result<(), TilingError> {
+        ["T", "B", "L", "R"]
+            .iter()
+            .zip(&self.fields)
+            .try_for_each(|(name, field)| {
+                let csv = field
+                    .chunks(self.size.max(1))
+                    .map(|row| {
+                        row.iter()
+                            .map(|p| p.to_string())
+                            .collect::<Vec<String>>()
+                            .join(",")
+                            + "\n"
+                    })
+                    .collect::<String>();
+                let path = format!("{prefix}_{name}.csv");
+                std::fs::write(&path, csv).map_err(|err| TilingError::io(&path, err))
+            })
+    }
+    /// Writes each field to `{prefix}_{T,B,L,R}.png`, shading the direction's
+    /// color by probability with `ts` pixels per cell.
+    pub fn save_heatmaps(
+        &self,
+        prefix: &str,
+        ts: usize,
+        colors: &Colors,
+    ) -> Result<(), TilingError> {
+        let size = self.size as u32;
+        [
+            ("T", colors.top),
+            ("B", colors.bottom),
+            ("L", colors.left),
+            ("R", colors.right),
+        ]
+        .iter()
+        .zip(&self.fields)
+        .try_for_each(|((name, color), field)| {
+            let im = RgbaImage::from_fn(size, size, |x, y| {
+                let p = field[(y * size + x) as usize];
+                Rgba([
+                    (color.0[0] as f64 * p) as u8,
+                    (color.0[1] as f64 * p) as u8,
+                    (color.0[2] as f64 * p) as u8,
+                    255,
+                ])
+            });
+            let im = resize(&im, size * ts as u32, size * ts as u32, FilterType::Nearest);
+            let path = format!("{prefix}_{name}.png");
+            im.save(&path).map_err(|source| TilingError::Image {
+                path: Some(path),
+                source,
+            })
+        })
+    }
+}
+
+impl Ensemble {
+    fn new(size: usize) -> Ensemble {
+        Ensemble {
+            samples: 0,
+            frequencies: Marginals::new(size),
+            counts: [0.0; 4],
+            squares: [0.0; 4],
+        }
+    }
+    /// Grows `samples` diamonds by `steps` steps in parallel and accumulates
+    /// their tiles. Each starts as `diamond` makes it from a seed drawn from
+    /// a generator seeded with `seed`, and `progress` is called with the
+    /// number of samples done after each.
+    pub fn sample(
+        samples: usize,
+        steps: usize,
+        seed: u64,
+        diamond: impl Fn(u64) -> Result<Diamond, TilingError> + Sync,
+        embed: Option<EmbeddableImage>,
+        progress: impl Fn(usize) + Sync,
+    ) -> Result<Ensemble, TilingError> {
+        let mut master = Pcg64::seed_from_u64(seed);
+        let seeds: Vec<u64> = (0..samples).map(|_| master.random()).collect();
+        let done = AtomicUsize::new(0);
+        seeds
+            .into_par_iter()
+            .try_fold(
+                || Ensemble::new(steps * 2),
+                |acc, s| {
+                    let mut x = diamond(s)?;
+                    (0..steps)
+                        .try_for_each(|i| x.step(if i == steps - 1 { &embed } else { &None }))?;
+                    progress(done.fetch_add(1, Ordering::Relaxed) + 1);
+                    Ok(acc.add(&x))
+                },
+            )
+            .try_reduce(|| Ensemble::new(steps * 2), |a, b| Ok(a.merge(b)))
+    }
+    fn add(mut self, x: &Diamond) -> Ensemble {
+        let mut counts = [0.0; 4];
+        x.tiles().for_each(|tile| {
+            let d = tile.dir as usize - 1;
+            self.frequencies.fields[d][tile.pos.0 * self.frequencies.size + tile.pos.1] += 1.0;
+            counts[d] += 1.0;
+        });
+        (0..4).for_each(|d| {
+            self.counts[d] += counts[d];
+            self.squares[d] += counts[d] * counts[d];
+        });
+        self.samples += 1;
+        self
+    }
+    fn merge(mut self, other: Ensemble) -> Ensemble {
+        (0..4).for_each(|d| {
+            self.frequencies.fields[d]
+                .iter_mut()
+                .zip(&other.frequencies.fields[d])
+                .for_each(|(a, b)| *a += b);
+            self.counts[d] += other.counts[d];
+            self.squares[d] += other.squares[d];
+        });
+        self.samples += other.samples;
+        self
+    }
+    /// Number of accumulated samples.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+    /// Placement frequencies divided by the number of samples.
+    pub fn densities(&self) -> Marginals {
+        let n = self.samples.max(1) as f64;
+        Marginals {
+            size: self.frequencies.size,
+            fields: std::array::from_fn(|d| {
+                self.frequencies.fields[d].iter().map(|f| f / n).collect()
+            }),
+        }
+    }
+    /// Mean and standard deviation of the number of tiles of each direction
+    /// per sample as CSV.
+    pub fn summary(&self) -> String {
+        let n = self.samples.max(1) as f64;
+        let total: f64 = self.counts.iter().sum();
+        ["T", "B", "L", "R"].iter().enumerate().fold(
+            "direction,mean,std,fraction\n".to_string(),
+            |acc, (d, name)| {
+                let mean = self.counts[d] / n;
+                let std = (self.squares[d] / n - mean * mean).max(0.0).sqrt();
+                let fraction = self.counts[d] / total.max(1.0);
+                acc + &format!("{name},{mean},{std},{fraction}\n")
+            },
+        )
+    }
+}
@@ -0,0 +1,502 @@
+use crate::diamond::{Coords, Direction, Tile, entropy_rng};
+use crate::error::TilingError;
+use crate::render::{Colors, rasterize, vectorize};
+use crate::tiling::{Tiling, couple_from_past};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const UNMATCHED: usize = usize::MAX;
+
+/// Domino tiling of an arbitrary region of the square grid.
+///
+/// The region is the set of cells marked in `mask`, a `rows x cols` grid,
+/// and `data` stores, for every cell that is the top-left cell of a domino,
+/// the domino's direction. Directions follow the checkerboard coloring with
+/// black cells at even `i + j`: a horizontal domino is `B` when its left cell
+/// is black and `T` otherwise, a vertical one `R` when its top cell is black
+/// and `L` otherwise, so that a mask of an Aztec diamond of even order is
+/// colored the way shuffling colors it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Region {
+    rows: usize,
+    cols: usize,
+    mask: Vec<bool>,
+    pub(crate) data: Vec<Option<Direction>>,
+    #[serde(default = "entropy_rng")]
+    pub(crate) rng: Pcg64,
+}
+
+impl Region {
+    /// Creates a region from a row-major `rows x cols` mask, tiled by some
+    /// arbitrary tiling until [`Region::sample`] is called.
+    ///
+    /// Fails if the mask has the wrong length or cannot be tiled.
+    pub fn new(
+        rows: usize,
+        cols: usize,
+        mask: Vec<bool>,
+        seed: u64,
+    ) -> Result<Region, TilingError> {
+        if mask.len() != rows * cols {
+            return Err(TilingError::Region(format!(
+                "mask has {} cells instead of {rows}x{cols}",
+                mask.len()
+            )));
+        }
+        let mut region = Region {
+            rows,
+            cols,
+            mask,
+            data: vec![None; rows * cols],
+            rng: Pcg64::seed_from_u64(seed),
+        };
+        region.data = region.matching()?;
+        Ok(region)
+    }
+    /// Creates a `rows x cols` rectangle.
+    pub fn rectangle(rows: usize, cols: usize, seed: u64) -> Result<Region, TilingError> {
+        Region::new(rows, cols, vec![true; rows * cols], seed)
+    }
+    /// Creates the Aztec diamond of order `order` in a square of side
+    /// `2 * order`.
+    pub fn aztec(order: usize, seed: u64) -> Result<Region, TilingError> {
+        let n = order as f64;
+        let mask = (0..2 * order)
+            .flat_map(|i| (0..2 * order).map(move |j| (i as f64 - n + 0.5, j as f64 - n + 0.5)))
+            .map(|(x, y)| x.abs() + y.abs() <= n)
+            .collect();
+        Region::new(2 * order, 2 * order, mask, seed)
+    }
+    /// Reads a mask from text, one line per row, where `#`, `X` and `1` mark
+    /// the cells of the region and any other character lies outside.
+    pub fn from_ascii(text: &str, seed: u64) -> Result<Region, TilingError> {
+        let lines: Vec<&str> = text.lines().collect();
+        let cols = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let mask = lines
+            .iter()
+            .flat_map(|l| {
+                l.chars()
+                    .map(|c| matches!(c, '#' | 'X' | '1'))
+                    .chain(std::iter::repeat(false))
+                    .take(cols)
+            })
+            .collect();
+        Region::new(lines.len(), cols, mask, seed)
+    }
+    /// Uses an image as a mask, one cell per pixel, where dark pixels belong
+    /// to the region.
+    pub fn from_image(image: &DynamicImage, seed: u64) -> Result<Region, TilingError> {
+        let luma = image.to_luma8();
+        let mask = luma.pixels().map(|p| p.0[0] < 128).collect();
+        Region::new(luma.height() as usize, luma.width() as usize, mask, seed)
+    }
+    /// Reads a mask from `path`, as an image if its extension names an image
+    /// format and as text otherwise.
+    pub fn load_mask(path: &str, seed: u64) -> Result<Region, TilingError> {
+        if ImageFormat::from_path(path).is_ok() {
+            let image = image::open(path).map_err(|source| TilingError::Embed {
+                path: Some(path.to_string()),
+                source,
+            })?;
+            Region::from_image(&image, seed)
+        } else {
+            let text = std::fs::read_to_string(path).map_err(|err| TilingError::io(path, err))?;
+            Region::from_ascii(&text, seed)
+        }
+    }
+    /// Restores a region serialized with [`Tiling::to_json`], checking that
+    /// the dominoes tile the region.
+    pub fn from_json(json: &str) -> Result<Region, TilingError> {
+        let region: Region = serde_json::from_str(json).map_err(TilingError::State)?;
+        let problems = region.problems();
+        if problems.is_empty() {
+            Ok(region)
+        } else {
+            Err(TilingError::Inconsistent(problems))
+        }
+    }
+    /// Descriptions of every way the dominoes fail to tile the region: lying
+    /// outside it, carrying the label of the other color, overlapping or
+    /// leaving cells uncovered.
+    fn problems(&self) -> Vec<String> {
+        let (rows, cols) = (self.rows, self.cols);
+        let cells = rows.checked_mul(cols);
+        if cells != Some(self.mask.len()) || cells != Some(self.data.len()) {
+            return vec![format!(
+                "{} mask cells and {} tile cells stored for a {rows}x{cols} grid",
+                self.mask.len(),
+                self.data.len()
+            )];
+        }
+        let mut problems = Vec::new();
+        let mut owners: Vec<Option<Coords>> = vec![None; rows * cols];
+        for i in 0..rows {
+            for j in 0..cols {
+                let Some(dir) = self.data[i * cols + j] else {
+                    continue;
+                };
+                let horizontal = matches!(dir, Direction::T | Direction::B);
+                let other = if horizontal { (i, j + 1) } else { (i + 1, j) };
+                if other.0 >= rows || other.1 >= cols {
+                    problems.push(format!(
+                        "domino at ({i}, {j}) pointing {dir:?} sticks out of the grid"
+                    ));
+                    continue;
+                }
+                let expected = Region::label((i, j), horizontal);
+                if dir != expected {
+                    problems.push(format!(
+                        "domino at ({i}, {j}) points {dir:?} where only {expected:?} can"
+                    ));
+                }
+                for (a, b) in [(i, j), other] {
+                    if !self.mask[a * cols + b] {
+                        problems.push(format!(
+                            "domino at ({i}, {j}) covers ({a}, {b}) outside the region"
+                        ));
+                    }
+                    let owner = &mut owners[a * cols + b];
+                    match owner {
+                        Some(first) => problems.push(format!(
+                            "domino at ({i}, {j}) overlaps the domino at {first:?} on ({a}, {b})"
+                        )),
+                        None => *owner = Some((i, j)),
+                    }
+                }
+            }
+        }
+        (0..rows * cols)
+            .filter(|&k| self.mask[k] && owners[k].is_none())
+            .for_each(|k| {
+                problems.push(format!("cell ({}, {}) is not covered", k / cols, k % cols))
+            });
+        problems
+    }
+    /// Reads a region saved with [`Tiling::save`].
+    pub fn load(path: &str) -> Result<Region, TilingError> {
+        let content = std::fs::read_to_string(path).map_err(|err| TilingError::io(path, err))?;
+        Region::from_json(&content)
+    }
+
+    /// Replaces the tiling with a uniformly random one by coupling from the
+    /// past and returns how many sweeps into the past were needed.
+    ///
+    /// The flip chain picks a random 2x2 block and, if two parallel dominoes
+    /// cover it, lays them out according to a fair coin. Comparing tilings
+    /// through their height functions, the chain is monotone, so it is run
+    /// from the highest and the lowest tiling with the same moves until both
+    /// end in the same tiling.
+    ///
+    /// Flips connect all tilings of a simply connected region. With holes
+    /// the sample is uniform among the tilings reachable from the initial
+    /// one, see [`Region::holes`].
+    pub fn sample(&mut self) -> usize {
+        let faces = self.faces();
+        if faces.is_empty() {
+            return 0;
+        }
+        let cols = self.cols;
+        let top = self.extremal(&faces, true);
+        let bottom = self.extremal(&faces, false);
+        let (data, sweeps) = couple_from_past(
+            &mut self.rng,
+            faces.len(),
+            top,
+            bottom,
+            |rng, upper, lower| {
+                let face = faces[rng.random_range(0..faces.len())];
+                let up = rng.random::<bool>();
+                Region::flip(cols, upper, face, up);
+                Region::flip(cols, lower, face, up);
+            },
+        );
+        self.data = data;
+        sweeps
+    }
+
+    /// Number of holes, i.e. groups of cells outside the region that are
+    /// enclosed by it, connecting cells that touch diagonally.
+    pub fn holes(&self) -> usize {
+        let mut seen = vec![false; self.rows * self.cols];
+        let mut holes = 0;
+        (0..self.rows * self.cols).for_each(|start| {
+            if self.mask[start] || seen[start] {
+                return;
+            }
+            seen[start] = true;
+            let mut stack = vec![start];
+            let mut enclosed = true;
+            while let Some(c) = stack.pop() {
+                let (i, j) = (c / self.cols, c % self.cols);
+                if i == 0 || j == 0 || i + 1 == self.rows || j + 1 == self.cols {
+                    enclosed = false;
+                }
+                (i.saturating_sub(1)..(i + 2).min(self.rows)).for_each(|a| {
+                    (j.saturating_sub(1)..(j + 2).min(self.cols)).for_each(|b| {
+                        let n = a * self.cols + b;
+                        if !self.mask[n] && !seen[n] {
+                            seen[n] = true;
+                            stack.push(n);
+                        }
+                    })
+                });
+            }
+            if enclosed {
+                holes += 1;
+            }
+        });
+        holes
+    }
+
+    /// Iterates over all dominoes of the tiling.
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        self.data.iter().enumerate().filter_map(|(c, dir)| {
+            dir.map(|dir| Tile {
+                pos: (c / self.cols, c % self.cols),
+                dir,
+            })
+        })
+    }
+    /// Number of rows of the mask.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    /// Number of columns of the mask.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+    /// Whether cell `(i, j)` belongs to the region.
+    pub fn contains(&self, i: usize, j: usize) -> bool {
+        i < self.rows && j < self.cols && self.mask[i * self.cols + j]
+    }
+
+    pub(crate) fn black(c: Coords) -> bool {
+        (c.0 + c.1).is_multiple_of(2)
+    }
+    fn neighbours(&self, c: usize) -> impl Iterator<Item = usize> + '_ {
+        let (i, j) = (c / self.cols, c % self.cols);
+        [
+            (i.wrapping_sub(1), j),
+            (i + 1, j),
+            (i, j.wrapping_sub(1)),
+            (i, j + 1),
+        ]
+        .into_iter()
+        .filter(|&(a, b)| self.contains(a, b))
+        .map(|(a, b)| a * self.cols + b)
+    }
+    /// Finds a tiling as a perfect matching between black and white cells
+    /// with the Hopcroft-Karp algorithm.
+    fn matching(&self) -> Result<Vec<Option<Direction>>, TilingError> {
+        let n = self.rows * self.cols;
+        let cols = self.cols;
+        let blacks: Vec<usize> = (0..n)
+            .filter(|&c| self.mask[c] && Region::black((c / cols, c % cols)))
+            .collect();
+        let whites = self.mask.iter().filter(|&&m| m).count() - blacks.len();
+        if whites != blacks.len() {
+            return Err(TilingError::Region(format!(
+                "{} black and {whites} white cells",
+                blacks.len()
+            )));
+        }
+        let mut mate = vec![UNMATCHED; n];
+        let mut dist = vec![usize::MAX; n];
+        loop {
+            // Layer the black cells by the length of the shortest
+            // alternating path from an unmatched one.
+            dist.fill(usize::MAX);
+            let mut queue: VecDeque<usize> = blacks
+                .iter()
+                .copied()
+                .filter(|&b| mate[b] == UNMATCHED)
+                .collect();
+            queue.iter().for_each(|&b| dist[b] = 0);
+            let mut limit = usize::MAX;
+            while let Some(b) = queue.pop_front() {
+                if dist[b] >= limit {
+                    continue;
+                }
+                self.neighbours(b).for_each(|w| match mate[w] {
+                    UNMATCHED => limit = limit.min(dist[b] + 1),
+                    m if dist[m] == usize::MAX => {
+                        dist[m] = dist[b] + 1;
+                        queue.push_back(m);
+                    }
+                    _ => {}
+                });
+            }
+            if limit == usize::MAX {
+                break;
+            }
+            blacks.iter().for_each(|&b| {
+                if mate[b] == UNMATCHED {
+                    self.augment(b, limit, &mut mate, &mut dist);
+                }
+            });
+        }
+        if let Some(&b) = blacks.iter().find(|&&b| mate[b] == UNMATCHED) {
+            return Err(TilingError::Region(format!(
+                "no domino tiling covers cell ({}, {})",
+                b / cols,
+                b % cols
+            )));
+        }
+        let mut data = vec![None; n];
+        blacks.iter().for_each(|&b| {
+            let anchor = b.min(mate[b]);
+            let horizontal = b / cols == mate[b] / cols;
+            data[anchor] = Some(Region::label((anchor / cols, anchor % cols), horizontal));
+        });
+        Ok(data)
+    }
+    /// Looks for an augmenting path from the unmatched black cell `root`
+    /// along the layers in `dist`, flipping it if found. Dead ends are
+    /// removed from the layering.
+    fn augment(&self, root: usize, limit: usize, mate: &mut [usize], dist: &mut [usize]) -> bool {
+        // Black cells on the path with the next neighbour to try, and the
+        // white cells leading from each to the next.
+        let mut stack = vec![(root, 0)];
+        let mut via: Vec<usize> = vec![];
+        while let Some((b, k)) = stack.last_mut() {
+            let b = *b;
+            let next = self.neighbours(b).nth(*k);
+            *k += 1;
+            match next {
+                None => {
+                    dist[b] = usize::MAX;
+                    stack.pop();
+                    via.pop();
+                }
+                Some(w) if mate[w] == UNMATCHED => {
+                    if dist[b] + 1 == limit {
+                        mate[w] = b;
+                        mate[b] = w;
+                        via.iter().zip(&stack).for_each(|(&w, &(b, _))| {
+                            mate[w] = b;
+                            mate[b] = w;
+                        });
+                        return true;
+                    }
+                }
+                Some(w) => {
+                    if dist[mate[w]] == dist[b] + 1 {
+                        via.push(w);
+                        stack.push((mate[w], 0));
+                    }
+                }
+            }
+        }
+        false
+    }
+    fn label(anchor: Coords, horizontal: bool) -> Direction {
+        match (horizontal, Region::black(anchor)) {
+            (true, true) => Direction::B,
+            (true, false) => Direction::T,
+            (false, true) => Direction::R,
+            (false, false) => Direction::L,
+        }
+    }
+    /// Top-left cells of the 2x2 blocks inside the region.
+    pub(crate) fn faces(&self) -> Vec<Coords> {
+        (0..self.rows.saturating_sub(1))
+            .flat_map(|i| (0..self.cols.saturating_sub(1)).map(move |j| (i, j)))
+            .filter(|&(i, j)| {
+                self.contains(i, j)
+                    && self.contains(i + 1, j)
+                    && self.contains(i, j + 1)
+                    && self.contains(i + 1, j + 1)
+            })
+            .collect()
+    }
+    /// Flips the tiling `data` to its highest or lowest height function.
+    fn extremal(&self, faces: &[Coords], up: bool) -> Vec<Option<Direction>> {
+        let mut data = self.data.clone();
+        while faces.iter().fold(false, |flipped, &face| {
+            Region::flip(self.cols, &mut data, face, up) | flipped
+        }) {}
+        data
+    }
+    /// Lays two parallel dominoes covering `face` out so that the height at
+    /// its center is raised if `up` and lowered otherwise, which makes them
+    /// horizontal on black faces and vertical on white ones when raising.
+    pub(crate) fn flip(
+        cols: usize,
+        data: &mut [Option<Direction>],
+        (i, j): Coords,
+        up: bool,
+    ) -> bool {
+        let at = |c: Coords| data[c.0 * cols + c.1];
+        let horizontal = |d| matches!(d, Some(Direction::T | Direction::B));
+        let vertical = |d| matches!(d, Some(Direction::L | Direction::R));
+        let was_horizontal = if horizontal(at((i, j))) && horizontal(at((i + 1, j))) {
+            true
+        } else if vertical(at((i, j))) && vertical(at((i, j + 1))) {
+            false
+        } else {
+            return false;
+        };
+        let now_horizontal = up == Region::black((i, j));
+        if now_horizontal == was_horizontal {
+            return false;
+        }
+        let second = if now_horizontal {
+            (i + 1, j)
+        } else {
+            (i, j + 1)
+        };
+        data[i * cols + j + 1] = None;
+        data[(i + 1) * cols + j] = None;
+        data[i * cols + j] = Some(Region::label((i, j), now_horizontal));
+        data[second.0 * cols + second.1] = Some(Region::label(second, now_horizontal));
+        true
+    }
+}
+
+impl Tiling for Region {
+    /// Renders the tiling with the same layout and colors as a [`Diamond`],
+    /// cells outside the region left gray.
+    ///
+    /// [`Diamond`]: crate::Diamond
+    fn render(&self, ts: usize, colors: &Colors) -> RgbaImage {
+        let tiles: Vec<Tile> = self.tiles().collect();
+        rasterize(self.rows, self.cols, &tiles, ts, colors)
+    }
+    fn svg(&self, ts: usize, colors: &Colors) -> String {
+        let tiles: Vec<Tile> = self.tiles().collect();
+        vectorize(self.rows, self.cols, &tiles, ts, colors)
+    }
+    fn to_json(&self) -> Result<String, TilingError> {
+        serde_json::to_string(self).map_err(TilingError::State)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_saved_regions() {
+        let mut x = Region::aztec(3, 5).expect("tileable");
+        x.sample();
+        let json = x.to_json().expect("json");
+        assert!(Region::from_json(&json).is_ok());
+    }
+
+    #[test]
+    fn rejects_broken_tilings() {
+        let x = Region::rectangle(2, 4, 5).expect("tileable");
+        let mut broken = x.clone();
+        broken.mask[0] = false;
+        broken.data[1] = broken.data[0];
+        match Region::from_json(&broken.to_json().expect("json")) {
+            Err(TilingError::Inconsistent(problems)) => {
+                assert!(problems.len() >= 2, "{problems:?}")
+            }
+            other => panic!("expected inconsistencies, got {:?}", other.err()),
+        }
+    }
+}
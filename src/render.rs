@@ -0,0 +1,242 @@
+use crate::diamond::{Direction, Tile};
+use crate::error::TilingError;
+use image::imageops::{FilterType, resize};
+use image::{DynamicImage, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut};
+use imageproc::rect::Rect;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// What [`Tiling::draw_image`](crate::Tiling::draw_image) does with the rendered picture.
+pub enum ImageAction {
+    /// Save the image to the given path. Raster images are encoded according
+    /// to the extension.
+    Save(String, RenderFormat),
+    /// Return the encoded image, PNG for raster images.
+    Return(RenderFormat),
+}
+
+/// How a tiling is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderFormat {
+    /// A bitmap, see [`Tiling::render`](crate::Tiling::render).
+    Raster,
+    /// A vector image with one shape per tile, see [`Tiling::svg`](crate::Tiling::svg).
+    Svg,
+}
+
+impl RenderFormat {
+    /// Picks the format matching the extension of `path`.
+    pub fn from_path(path: &str) -> RenderFormat {
+        match std::path::Path::new(path).extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("svg") => RenderFormat::Svg,
+            _ => RenderFormat::Raster,
+        }
+    }
+    /// Extension of files in this format, `png` for raster images.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RenderFormat::Raster => "png",
+            RenderFormat::Svg => "svg",
+        }
+    }
+    /// MIME type of the bytes returned by [`ImageAction::Return`].
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RenderFormat::Raster => "image/png",
+            RenderFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+impl FromStr for RenderFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<RenderFormat, String> {
+        match input.to_ascii_lowercase().as_str() {
+            "png" | "raster" => Ok(RenderFormat::Raster),
+            "svg" => Ok(RenderFormat::Svg),
+            _ => Err(format!("{input}: expected png or svg")),
+        }
+    }
+}
+
+/// Palette used to draw each domino orientation and the grid between tiles.
+#[derive(Clone, Copy, Debug)]
+pub struct Colors {
+    /// Fill of north-going dominoes and of horizontal lozenges.
+    pub top: Rgba<u8>,
+    /// Fill of south-going dominoes.
+    pub bottom: Rgba<u8>,
+    /// Fill of west-going dominoes and of lozenges facing `x`.
+    pub left: Rgba<u8>,
+    /// Fill of east-going dominoes and of lozenges facing `y`.
+    pub right: Rgba<u8>,
+    /// Outline drawn around every tile.
+    pub grid: Rgba<u8>,
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            top: Rgba([255, 0, 0, 255]),
+            bottom: Rgba([0, 0, 255, 255]),
+            left: Rgba([255, 255, 0, 255]),
+            right: Rgba([0, 255, 0, 255]),
+            grid: Rgba([0, 0, 0, 255]),
+        }
+    }
+}
+
+impl Colors {
+    /// Builds a palette from `0xRRGGBBAA` values.
+    pub fn new(t: u32, b: u32, l: u32, r: u32, g: u32) -> Colors {
+        Colors {
+            top: Colors::int_to_color(t),
+            bottom: Colors::int_to_color(b),
+            left: Colors::int_to_color(l),
+            right: Colors::int_to_color(r),
+            grid: Colors::int_to_color(g),
+        }
+    }
+    pub(crate) fn to_svg(c: Rgba<u8>) -> String {
+        let [r, g, b, a] = c.0;
+        if a == 255 {
+            format!("#{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("rgba({r},{g},{b},{:.3})", a as f64 / 255.0)
+        }
+    }
+    fn int_to_color(c: u32) -> Rgba<u8> {
+        Rgba([
+            ((c >> 24) & 0xff) as u8,
+            ((c >> 16) & 0xff) as u8,
+            ((c >> 8) & 0xff) as u8,
+            (c & 0xff) as u8,
+        ])
+    }
+}
+
+/// Side in pixels of one cell of a rendered domino tiling with tile size
+/// `ts`, which large tiles round down to even.
+pub(crate) fn cell_pixels(ts: usize) -> usize {
+    if ts > 16 { ts / 2 * 2 } else { ts }
+}
+
+/// Rasterizes a `rows x cols` grid of cells covered by `tiles`.
+pub(crate) fn rasterize(
+    rows: usize,
+    cols: usize,
+    tiles: &[Tile],
+    ts: usize,
+    colors: &Colors,
+) -> RgbaImage {
+    let tile_size = if ts > 16 { ts / 2 } else { ts };
+    let mut im = RgbaImage::from_pixel(
+        (cols * tile_size) as u32,
+        (rows * tile_size) as u32,
+        Rgba([128, 128, 128, 255]),
+    );
+    tiles.iter().for_each(|tile| {
+        let (i, j) = tile.pos;
+        let (src, w, h) = match tile.dir {
+            Direction::T => (colors.top, 2, 1),
+            Direction::B => (colors.bottom, 2, 1),
+            Direction::L => (colors.left, 1, 2),
+            Direction::R => (colors.right, 1, 2),
+        };
+        // Rectangles cannot be empty, and tiles of one or two pixels have
+        // no inside to fill.
+        if tile_size > 0 {
+            draw_hollow_rect_mut(
+                &mut im,
+                Rect::at((j * tile_size) as i32, (i * tile_size) as i32)
+                    .of_size((w * tile_size) as u32, (h * tile_size) as u32),
+                colors.grid,
+            );
+        }
+        if tile_size > 2 {
+            draw_filled_rect_mut(
+                &mut im,
+                Rect::at((j * tile_size) as i32 + 1, (i * tile_size) as i32 + 1)
+                    .of_size((w * tile_size - 2) as u32, (h * tile_size - 2) as u32),
+                src,
+            );
+        }
+    });
+    if ts > 16 {
+        im = resize(&im, im.width() * 2, im.height() * 2, FilterType::Nearest);
+    }
+    im
+}
+
+/// Draws a `rows x cols` grid of cells covered by `tiles` as an SVG document
+/// with `ts` units per cell, one rectangle per tile outlined in the grid
+/// color.
+pub(crate) fn vectorize(
+    rows: usize,
+    cols: usize,
+    tiles: &[Tile],
+    ts: usize,
+    colors: &Colors,
+) -> String {
+    let (width, height) = (cols * ts, rows * ts);
+    let grid = Colors::to_svg(colors.grid);
+    let fills = [colors.top, colors.bottom, colors.left, colors.right].map(Colors::to_svg);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" shape-rendering=\"crispEdges\">\n\
+         <rect width=\"{width}\" height=\"{height}\" fill=\"#808080\"/>\n\
+         <g stroke=\"{grid}\" stroke-width=\"1\">\n"
+    );
+    tiles.iter().for_each(|tile| {
+        let (i, j) = tile.pos;
+        let (w, h) = match tile.dir {
+            Direction::T | Direction::B => (2, 1),
+            Direction::L | Direction::R => (1, 2),
+        };
+        // The stroke is inset by half its width so it stays inside the
+        // tile, as in the raster rendering.
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}.5\" y=\"{}.5\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            j * ts,
+            i * ts,
+            (w * ts).saturating_sub(1),
+            (h * ts).saturating_sub(1),
+            fills[tile.dir as usize - 1]
+        );
+    });
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+/// Carries out `action` with the raster or vector drawing it asks for.
+pub(crate) fn draw(
+    render: impl FnOnce() -> RgbaImage,
+    svg: impl FnOnce() -> String,
+    action: ImageAction,
+) -> Result<Option<Vec<u8>>, TilingError> {
+    match action {
+        ImageAction::Save(s, RenderFormat::Svg) => {
+            std::fs::write(&s, svg()).map_err(|err| TilingError::io(&s, err))?;
+            Ok(None)
+        }
+        ImageAction::Save(s, RenderFormat::Raster) => {
+            render().save(&s).map_err(|source| TilingError::Image {
+                path: Some(s),
+                source,
+            })?;
+            Ok(None)
+        }
+        ImageAction::Return(RenderFormat::Svg) => Ok(Some(svg().into_bytes())),
+        ImageAction::Return(RenderFormat::Raster) => {
+            let mut bytes: Vec<u8> = Vec::new();
+            DynamicImage::ImageRgba8(render())
+                .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+                .map_err(|source| TilingError::Image { path: None, source })?;
+            Ok(Some(bytes))
+        }
+    }
+}
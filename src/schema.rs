@@ -0,0 +1,319 @@
+use crate::diamond::{Coords, Diamond, Direction, entropy_rng};
+use crate::error::TilingError;
+use crate::expression::Expression;
+use crate::weights::Weights;
+use rand_pcg::Pcg64;
+use serde::de::DeserializeOwned;
+use serde::de::{Error as _, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Version of the on-disk schema written by [`encode`].
+///
+/// The schema is kept apart from the in-memory layout of [`Diamond`] so that
+/// it only changes on purpose. Every version has its own struct, frozen once
+/// released, and [`decode`] migrates older documents one version at a time.
+pub(crate) const FORMAT_VERSION: u64 = 1;
+
+/// The unversioned layout of the first releases: `tiles` maps an id to each
+/// domino with its top-left cell in the bounding square, and `data` holds the
+/// id covering every cell. The dominoes are rebuilt from `tiles` alone. The
+/// random generator and weights were added later and may be missing.
+#[derive(Deserialize)]
+struct DiamondV0 {
+    size: usize,
+    tiles: HashMap<usize, TileV0>,
+    current_square: Coords,
+    p: f64,
+    #[serde(default = "entropy_rng")]
+    rng: Pcg64,
+    #[serde(default)]
+    weights: Option<Weights>,
+}
+
+#[derive(Deserialize)]
+struct TileV0 {
+    pos: Coords,
+    dir: Direction,
+}
+
+/// The unversioned layout that replaced [`DiamondV0`], a copy of the fields
+/// of `Diamond` once dominoes were stored in a dense direction grid. The
+/// expression and step count were added later and may be missing.
+#[derive(Deserialize)]
+struct DiamondGrid {
+    size: usize,
+    capacity: usize,
+    origin: Coords,
+    data: Vec<Option<Direction>>,
+    current_square: Coords,
+    p: f64,
+    rng: Pcg64,
+    weights: Option<Weights>,
+    #[serde(default)]
+    expression: Option<Expression>,
+    #[serde(default)]
+    steps: usize,
+}
+
+/// Version 1: the order, the parameters and one string of direction letters
+/// per row of the diamond.
+#[derive(Serialize, Deserialize)]
+struct DiamondV1 {
+    format_version: u64,
+    order: usize,
+    p: f64,
+    steps: usize,
+    rng: Pcg64,
+    weights: Option<Weights>,
+    expression: Option<Expression>,
+    rows: Vec<String>,
+}
+
+/// Just the version of a document, read before the rest. Going through
+/// `serde_json::Value` would lose the precision of the random generator.
+/// Unversioned documents are told apart by their `tiles`.
+#[derive(Deserialize)]
+struct Version {
+    format_version: Option<Value>,
+    tiles: Option<IgnoredAny>,
+}
+
+fn parse<T: DeserializeOwned>(json: &str) -> Result<T, TilingError> {
+    serde_json::from_str(json).map_err(TilingError::State)
+}
+
+fn malformed(message: String) -> TilingError {
+    TilingError::State(serde_json::Error::custom(message))
+}
+
+fn letter(dir: Option<Direction>) -> char {
+    match dir {
+        Some(Direction::T) => 'T',
+        Some(Direction::B) => 'B',
+        Some(Direction::L) => 'L',
+        Some(Direction::R) => 'R',
+        None => '.',
+    }
+}
+
+fn direction(letter: char) -> Option<Option<Direction>> {
+    match letter {
+        'T' => Some(Some(Direction::T)),
+        'B' => Some(Some(Direction::B)),
+        'L' => Some(Some(Direction::L)),
+        'R' => Some(Some(Direction::R)),
+        '.' => Some(None),
+        _ => None,
+    }
+}
+
+impl From<&Diamond> for DiamondV1 {
+    fn from(x: &Diamond) -> DiamondV1 {
+        DiamondV1 {
+            format_version: FORMAT_VERSION,
+            order: x.order(),
+            p: x.p,
+            steps: x.steps,
+            rng: x.rng.clone(),
+            weights: x.weights.clone(),
+            expression: x.expression.clone(),
+            rows: (0..x.size)
+                .map(|i| {
+                    x.span(i)
+                        .map(|j| letter(x.tile_at(i, j).map(|tile| tile.dir)))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<DiamondV0> for DiamondV1 {
+    type Error = TilingError;
+
+    /// Places the dominoes of the tile map, which must tile the diamond.
+    fn try_from(v0: DiamondV0) -> Result<DiamondV1, TilingError> {
+        if v0.size % 2 == 1 {
+            return Err(TilingError::Inconsistent(vec![format!(
+                "size {} must be even",
+                v0.size
+            )]));
+        }
+        let dominoes: HashMap<Coords, Direction> =
+            v0.tiles.values().map(|tile| (tile.pos, tile.dir)).collect();
+        let mut placed = 0;
+        let mut x = Diamond::from_dominoes(v0.size / 2, v0.p, |c| {
+            let dir = dominoes.get(&c).copied();
+            placed += usize::from(dir.is_some());
+            Ok(dir)
+        })?;
+        if placed != v0.tiles.len() {
+            return Err(TilingError::Inconsistent(vec![format!(
+                "{} of {} dominoes overlap or lie outside the diamond",
+                v0.tiles.len() - placed,
+                v0.tiles.len()
+            )]));
+        }
+        x.current_square = v0.current_square;
+        x.rng = v0.rng;
+        x.weights = v0.weights;
+        x.validate()?;
+        Ok(DiamondV1::from(&x))
+    }
+}
+
+impl TryFrom<DiamondGrid> for DiamondV1 {
+    type Error = TilingError;
+
+    /// Rebuilds the diamond from its raw storage, which must be consistent.
+    fn try_from(grid: DiamondGrid) -> Result<DiamondV1, TilingError> {
+        let x = Diamond {
+            size: grid.size,
+            capacity: grid.capacity,
+            origin: grid.origin,
+            data: grid.data,
+            current_square: grid.current_square,
+            p: grid.p,
+            rng: grid.rng,
+            weights: grid.weights,
+            expression: grid.expression,
+            steps: grid.steps,
+        };
+        x.validate()?;
+        Ok(DiamondV1::from(&x))
+    }
+}
+
+impl TryFrom<DiamondV1> for Diamond {
+    type Error = TilingError;
+
+    fn try_from(v1: DiamondV1) -> Result<Diamond, TilingError> {
+        let order = v1.order;
+        let rows: Vec<Vec<char>> = v1.rows.iter().map(|row| row.chars().collect()).collect();
+        if Some(rows.len()) != order.checked_mul(2) {
+            return Err(malformed(format!(
+                "{} rows for a diamond of order {order}",
+                rows.len()
+            )));
+        }
+        // Row `i` of an order `n` diamond is `2 * min(i + 1, 2n - i)` cells
+        // wide.
+        if let Some(i) = (0..rows.len()).find(|&i| rows[i].len() != 2 * (i + 1).min(2 * order - i))
+        {
+            return Err(malformed(format!(
+                "row {i} has {} cells, expected {}",
+                rows[i].len(),
+                2 * (i + 1).min(2 * order - i)
+            )));
+        }
+        let start = |i: usize| order - 1 - i.min(2 * order - 1 - i);
+        let mut x = Diamond::from_dominoes(order, v1.p, |(i, j)| {
+            let c = rows[i][j - start(i)];
+            direction(c).ok_or_else(|| malformed(format!("unknown letter {c:?} at ({i}, {j})")))
+        })?;
+        // Both cells of a domino carry its letter.
+        for (i, row) in rows.iter().enumerate() {
+            for (k, c) in row.iter().enumerate() {
+                let j = start(i) + k;
+                let expected = letter(x.tile_at(i, j).map(|tile| tile.dir));
+                if *c != expected {
+                    return Err(malformed(format!(
+                        "cell ({i}, {j}) is {c:?} but its domino is {expected:?}"
+                    )));
+                }
+            }
+        }
+        x.rng = v1.rng;
+        x.weights = v1.weights;
+        x.expression = v1.expression;
+        x.steps = v1.steps;
+        Ok(x)
+    }
+}
+
+/// Serializes `x` in the current version of the schema.
+pub(crate) fn encode(x: &Diamond) -> Result<String, TilingError> {
+    serde_json::to_string(&DiamondV1::from(x)).map_err(TilingError::State)
+}
+
+/// Parses a state in any version of the schema, taking documents without a
+/// `format_version` for version 0 if they have `tiles` and for the dense
+/// grid layout otherwise.
+pub(crate) fn decode(json: &str) -> Result<Diamond, TilingError> {
+    let probe = parse::<Version>(json)?;
+    let Some(version) = probe.format_version else {
+        let v1: DiamondV1 = match probe.tiles {
+            Some(_) => parse::<DiamondV0>(json)?.try_into()?,
+            None => parse::<DiamondGrid>(json)?.try_into()?,
+        };
+        return v1.try_into();
+    };
+    let version = version
+        .as_u64()
+        .ok_or_else(|| malformed(format!("invalid format_version {version}")))?;
+    let v1: DiamondV1 = match version {
+        1 => parse(json)?,
+        version if version > FORMAT_VERSION => {
+            return Err(malformed(format!(
+                "format_version {version} is newer than the supported {FORMAT_VERSION}"
+            )));
+        }
+        _ => return Err(malformed(format!("invalid format_version {version}"))),
+    };
+    v1.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(x: &Diamond) -> Vec<String> {
+        DiamondV1::from(x).rows
+    }
+
+    #[test]
+    fn reads_tile_maps() {
+        let x = decode(include_str!("../tests/fixtures/tile-map.json")).expect("tile map");
+        assert_eq!(x.p, 0.3);
+        assert_eq!(rows(&x), ["TT", "LRTT", "LLRBBR", "LLBBRR", "LLRR", "LR"]);
+    }
+
+    #[test]
+    fn reads_grids() {
+        let x = decode(include_str!("../tests/fixtures/grid.json")).expect("grid");
+        assert_eq!(x.steps, 3);
+        assert_eq!(rows(&x), ["TT", "TTLR", "LBBLRR", "LLRLRR", "LRLR", "BB"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let x = decode(include_str!("../tests/fixtures/grid.json")).expect("grid");
+        let json = encode(&x).expect("encoding");
+        assert_eq!(
+            encode(&decode(&json).expect("decoding")).expect("encoding"),
+            json
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let x = decode(include_str!("../tests/fixtures/grid.json")).expect("grid");
+        let json = encode(&x).expect("encoding");
+        for version in ["0", "2", "\"1\""] {
+            let json = json.replace(
+                "\"format_version\":1",
+                &format!("\"format_version\":{version}"),
+            );
+            assert!(matches!(decode(&json), Err(TilingError::State(_))));
+        }
+    }
+
+    #[test]
+    fn rejects_overlapping_tiles() {
+        let json = include_str!("../tests/fixtures/tile-map.json")
+            .replace(r#""pos":[4,3]"#, r#""pos":[4,2]"#);
+        assert!(matches!(decode(&json), Err(TilingError::Inconsistent(_))));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::TilingError;
+use crate::render::{Colors, ImageAction, draw};
+use image::RgbaImage;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+
+/// What every family of tilings offers for drawing and saving, so that
+/// domino and lozenge tilings can be handled alike.
+pub trait Tiling {
+    /// Rasterizes the tiling with `ts` pixels per unit.
+    fn render(&self, ts: usize, colors: &Colors) -> RgbaImage;
+
+    /// Draws the tiling as an SVG document with `ts` units per unit, one
+    /// shape per tile outlined in the grid color.
+    fn svg(&self, ts: usize, colors: &Colors) -> String;
+
+    /// Serializes the tiling together with its random generator state.
+    fn to_json(&self) -> Result<String, TilingError>;
+
+    /// Renders the tiling and either saves it or returns the encoded image.
+    fn draw_image(
+        &self,
+        ts: usize,
+        colors: &Colors,
+        action: ImageAction,
+    ) -> Result<Option<Vec<u8>>, TilingError> {
+        draw(|| self.render(ts, colors), || self.svg(ts, colors), action)
+    }
+
+    /// Writes the tiling as JSON to `path`.
+    fn save(&self, path: &str) -> Result<(), TilingError> {
+        std::fs::write(path, self.to_json()?).map_err(|err| TilingError::io(path, err))
+    }
+}
+
+/// Coupling from the past for a monotone chain with `sweep` moves per sweep,
+/// returning the exact sample and how many sweeps into the past were needed.
+///
+/// `update` draws a move from the generator and applies it to both states,
+/// which start from the `top` and `bottom` of the state space. Each attempt
+/// goes twice as far back as the previous one, reusing the moves of the
+/// recent past, which are replayed from one seed per epoch rather than
+/// stored.
+pub(crate) fn couple_from_past<S: Clone + PartialEq>(
+    rng: &mut Pcg64,
+    sweep: usize,
+    top: S,
+    bottom: S,
+    mut update: impl FnMut(&mut Pcg64, &mut S, &mut S),
+) -> (S, usize) {
+    let mut seeds: Vec<u64> = vec![];
+    loop {
+        seeds.push(rng.random());
+        let mut upper = top.clone();
+        let mut lower = bottom.clone();
+        seeds.iter().enumerate().rev().for_each(|(epoch, &seed)| {
+            let mut rng = Pcg64::seed_from_u64(seed);
+            (0..sweep << epoch.saturating_sub(1))
+                .for_each(|_| update(&mut rng, &mut upper, &mut lower));
+        });
+        if upper == lower {
+            return (upper, 1 << (seeds.len() - 1));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::diamond::{Coords, Diamond, Direction};
+use crate::error::TilingError;
+
+impl Diamond {
+    /// Checks that the state is one shuffling can continue from, as states
+    /// read from files may have been edited or truncated.
+    ///
+    /// The storage must match the capacity with the diamond centered in it,
+    /// every domino must lie inside the diamond, point the way its position
+    /// calls for and not overlap another, every cell must be covered, the
+    /// search position must be where steps leave it, and the probability and
+    /// weights must be usable. All problems found are listed in the returned
+    /// [`TilingError::Inconsistent`].
+    pub fn validate(&self) -> Result<(), TilingError> {
+        let mut problems = self.storage_problems();
+        if problems.is_empty() {
+            problems.extend(self.tiling_problems());
+        }
+        // Every step ends its search at the same place, the only one a saved
+        // state can hold.
+        let start = (0, self.order());
+        if self.current_square != start {
+            problems.push(format!(
+                "search position {:?} should be {start:?} between steps",
+                self.current_square
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.p) {
+            problems.push(format!("probability {} is not within [0, 1]", self.p));
+        }
+        if let Some(weights) = &self.weights {
+            problems.extend(weights.problems());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(TilingError::Inconsistent(problems))
+        }
+    }
+    /// Problems with the sizes and the window, which must be sound before any
+    /// cell can be looked at.
+    fn storage_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.size % 2 == 1 || self.capacity % 2 == 1 {
+            problems.push(format!(
+                "size {} and capacity {} must both be even",
+                self.size, self.capacity
+            ));
+        }
+        if self.size > self.capacity {
+            problems.push(format!(
+                "size {} exceeds capacity {}",
+                self.size, self.capacity
+            ));
+        } else {
+            let offset = (self.capacity - self.size) / 2;
+            if self.origin != (offset, offset) {
+                problems.push(format!(
+                    "origin {:?} does not center the diamond, expected {:?}",
+                    self.origin,
+                    (offset, offset)
+                ));
+            }
+        }
+        if self.data.len() != Diamond::cells(self.capacity) {
+            problems.push(format!(
+                "{} cells stored where capacity {} needs {}",
+                self.data.len(),
+                self.capacity,
+                Diamond::cells(self.capacity)
+            ));
+        }
+        problems
+    }
+    /// Problems with the dominoes themselves.
+    fn tiling_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let order = self.order();
+        let mut owners: Vec<Option<Coords>> = vec![None; self.size * self.size];
+        let mut inside = 0;
+        for i in 0..self.size {
+            for j in self.span(i) {
+                let Some(dir) = self.at(i, j) else {
+                    continue;
+                };
+                inside += 1;
+                let other = match dir {
+                    Direction::T | Direction::B => (i, j + 1),
+                    Direction::L | Direction::R => (i + 1, j),
+                };
+                if !self.contains(other.0, other.1) {
+                    problems.push(format!(
+                        "domino at ({i}, {j}) pointing {dir:?} sticks out of the diamond"
+                    ));
+                    continue;
+                }
+                // The first domino of an odd block moves up or left.
+                let first = (i + j + order) % 2 == 1;
+                let expected = match (dir, first) {
+                    (Direction::T | Direction::B, true) => Direction::T,
+                    (Direction::T | Direction::B, false) => Direction::B,
+                    (Direction::L | Direction::R, true) => Direction::L,
+                    (Direction::L | Direction::R, false) => Direction::R,
+                };
+                if dir != expected {
+                    problems.push(format!(
+                        "domino at ({i}, {j}) points {dir:?} where only {expected:?} can"
+                    ));
+                }
+                [(i, j), other].into_iter().for_each(|(a, b)| {
+                    let owner = &mut owners[a * self.size + b];
+                    match owner {
+                        Some(first) => problems.push(format!(
+                            "domino at ({i}, {j}) overlaps the domino at {first:?} on ({a}, {b})"
+                        )),
+                        None => *owner = Some((i, j)),
+                    }
+                });
+            }
+        }
+        for i in 0..self.size {
+            for j in self.span(i) {
+                if owners[i * self.size + j].is_none() {
+                    problems.push(format!("cell ({i}, {j}) is not covered"));
+                }
+            }
+        }
+        let outside = self.data.iter().filter(|d| d.is_some()).count() - inside;
+        if outside > 0 {
+            problems.push(format!("{outside} dominoes stored outside the diamond"));
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grown() -> Diamond {
+        let mut x = Diamond::new(0.5, 8, 7, None);
+        x.generate(4, None, |_| {}).expect("shuffling");
+        x
+    }
+
+    #[test]
+    fn accepts_shuffled_diamonds() {
+        assert!(grown().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_edited_states() {
+        let mut x = grown();
+        x.current_square = (1, 1);
+        x.p = 1.5;
+        let (i, j) = (0..x.size)
+            .flat_map(|i| x.span(i).map(move |j| (i, j)))
+            .find(|&(i, j)| x.at(i, j).is_some())
+            .expect("a domino");
+        *x.at_ref(i, j) = None;
+        match x.validate() {
+            Err(TilingError::Inconsistent(problems)) => {
+                assert_eq!(problems.len(), 4, "{problems:?}");
+            }
+            other => panic!("expected inconsistencies, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use actix_extract_multipart::*;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::{Next, from_fn};
+use actix_web::{App, HttpResponse, HttpServer, get, http::header, post, web};
+use rand::random;
+use serde::Deserialize;
+use tilings::{
+    Colors, Diamond, EmbeddableImage, Hexagon, ImageAction, RenderFormat, Tiling, TilingError,
+    Weights,
+};
+
+/// What a single request may ask of the server.
+///
+/// Sampling a hexagon takes time growing with its volume, so `max_side` is
+/// kept well below `max_steps`, 32 by default against 256 steps. Weights take
+/// memory and time proportional to their period times the steps, hence
+/// `max_weight_period`.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Most shuffling steps of a diamond.
+    pub max_steps: usize,
+    /// Longest side of a hexagon.
+    pub max_side: usize,
+    /// Most blocks in one period of a weight pattern.
+    pub max_weight_period: usize,
+    /// Largest tile size in pixels. Tiles are at least
+    /// [`MIN_TILE_SIZE`](Limits::MIN_TILE_SIZE) pixels whatever the limit.
+    pub max_tile_size: usize,
+    /// Largest request body in bytes.
+    pub max_upload: usize,
+}
+
+impl Limits {
+    /// Smallest tile size in pixels, the least that leaves room for a
+    /// tile's color inside its outline.
+    pub const MIN_TILE_SIZE: usize = 3;
+
+    /// The response turning away tiles smaller than
+    /// [`MIN_TILE_SIZE`](Limits::MIN_TILE_SIZE) or larger than
+    /// `max_tile_size`.
+    fn reject_size(&self, size: usize) -> Option<HttpResponse> {
+        (!(Limits::MIN_TILE_SIZE..=self.max_tile_size).contains(&size)).then(|| {
+            HttpResponse::BadRequest().body(format!(
+                "tiles are between {} and {} pixels",
+                Limits::MIN_TILE_SIZE,
+                self.max_tile_size
+            ))
+        })
+    }
+    /// The response turning away diamonds without steps or with more than
+    /// `max_steps`, or tiles of a rejected size.
+    fn reject(&self, steps: usize, size: usize) -> Option<HttpResponse> {
+        if steps == 0 {
+            Some(HttpResponse::BadRequest().body("at least 1 step is needed"))
+        } else if steps > self.max_steps {
+            Some(
+                HttpResponse::BadRequest()
+                    .body(format!("at most {} steps are allowed", self.max_steps)),
+            )
+        } else {
+            self.reject_size(size)
+        }
+    }
+    /// The response turning away weight patterns with more than
+    /// `max_weight_period` blocks in a period.
+    fn reject_weights(&self, weights: &Weights) -> Option<HttpResponse> {
+        (weights.period() > self.max_weight_period).then(|| {
+            HttpResponse::BadRequest().body(format!(
+                "weight patterns have at most {} blocks",
+                self.max_weight_period
+            ))
+        })
+    }
+    /// The response turning away hexagons with a side longer than
+    /// `max_side`, or tiles of a rejected size.
+    fn reject_hexagon(&self, sides: [usize; 3], size: usize) -> Option<HttpResponse> {
+        if sides.iter().any(|&side| side > self.max_side) {
+            Some(
+                HttpResponse::BadRequest()
+                    .body(format!("hexagon sides are at most {}", self.max_side)),
+            )
+        } else {
+            self.reject_size(size)
+        }
+    }
+}
+
+/// Turns away bodies over `max_upload` bytes before they are read, and
+/// uploads that do not declare their length.
+async fn limit_upload<B: MessageBody + 'static>(
+    limits: web::Data<Limits>,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+    let rejection = match length {
+        Some(length) if length > limits.max_upload => Some(
+            HttpResponse::PayloadTooLarge()
+                .body(format!("uploads are at most {} bytes", limits.max_upload)),
+        ),
+        None if req.method() == Method::POST => {
+            Some(HttpResponse::LengthRequired().body("uploads must declare their length"))
+        }
+        _ => None,
+    };
+    match rejection {
+        Some(response) => Ok(req.into_response(response).map_into_right_body()),
+        None => next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body),
+    }
+}
+
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        Option::<serde_json::Value>::deserialize(deserializer)?.map(|v| match v {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        }),
+    )
+}
+
+fn error_response(err: TilingError) -> HttpResponse {
+    if err.is_input_error() {
+        HttpResponse::BadRequest().body(err.to_string())
+    } else {
+        HttpResponse::InternalServerError().body(err.to_string())
+    }
+}
+
+fn image(x: &dyn Tiling, size: usize, format: RenderFormat) -> Result<Vec<u8>, TilingError> {
+    Ok(
+        x.draw_image(size, &Colors::default(), ImageAction::Return(format))?
+            .unwrap_or_default(),
+    )
+}
+
+fn format_or_png<'de, D>(deserializer: D) -> Result<RenderFormat, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map_or(Ok(RenderFormat::Raster), |s| s.parse())
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    fname: Option<File>,
+    steps: usize,
+    size: usize,
+    p: usize,
+    seed: Option<u64>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    weights: Option<String>,
+    #[serde(default = "png_format", deserialize_with = "format_or_png")]
+    format: RenderFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Query {
+    #[serde(default = "png_format", deserialize_with = "format_or_png")]
+    format: RenderFormat,
+}
+
+fn png_format() -> RenderFormat {
+    RenderFormat::Raster
+}
+
+#[post("/")]
+async fn index_post(params: Multipart<Params>, limits: web::Data<Limits>) -> HttpResponse {
+    if let Some(response) = limits.reject(params.steps, params.size) {
+        return response;
+    }
+    let weights = match params
+        .weights
+        .as_deref()
+        .map(str::parse::<Weights>)
+        .transpose()
+    {
+        Ok(weights) => weights,
+        Err(err) => return error_response(TilingError::Weights(err)),
+    };
+    if let Some(response) = weights.as_ref().and_then(|w| limits.reject_weights(w)) {
+        return response;
+    }
+    let mut x = Diamond::new(
+        params.p as f64 / 100.0f64,
+        params.steps * 2,
+        params.seed.unwrap_or_else(random),
+        weights.map(|w| w.with_order(params.steps)),
+    );
+    let embed = params
+        .fname
+        .as_ref()
+        .map(|f| EmbeddableImage::FileBytes(f.data().to_vec()));
+    let f = match x
+        .generate(params.steps, embed, |_| {})
+        .and_then(|()| image(&x, params.size, params.format))
+    {
+        Ok(f) => f,
+        Err(err) => return error_response(err),
+    };
+    HttpResponse::Ok()
+        .append_header(header::ContentDisposition::attachment(format!(
+            "image.{}",
+            params.format.extension()
+        )))
+        .content_type(params.format.content_type())
+        .body(f)
+}
+
+#[get("/")]
+async fn index_get() -> HttpResponse {
+    let html = r#"<!DOCTYPE html>
+    <html>
+    <body>
+
+    <h2>Tilings</h2>
+
+    <form method="post" action="/" enctype="multipart/form-data">
+      <label for="fname">Image:</label><br>
+      <input type="file" id="fname" name="fname" accept="image/png, image/jpeg"><br>
+      <label for="lname">Steps:</label><br>
+      <input type="number" id="steps" name="steps" value="256"><br>
+      <label for="lname">Size:</label><br>
+      <input type="number" id="size" name="size" value="4"><br>
+      <label for="lname">Probability (%):</label><br>
+      <input type="number" id="p" name="p" value="50" min="0" max="100"><br>
+      <label for="seed">Seed (optional):</label><br>
+      <input type="number" id="seed" name="seed" min="0"><br>
+      <label for="weights">Periodic weights (optional, e.g. 1;0.5):</label><br>
+      <input type="text" id="weights" name="weights"><br>
+      <label for="format">Format:</label><br>
+      <select id="format" name="format">
+        <option value="png">PNG</option>
+        <option value="svg">SVG</option>
+      </select><br><br>
+      <input type="submit" value="Submit">
+    </form>
+
+    </body>
+    </html>"#;
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+#[get("/{steps}/{size}")]
+async fn index(
+    path: web::Path<(usize, usize)>,
+    query: web::Query<Query>,
+    limits: web::Data<Limits>,
+) -> HttpResponse {
+    let (steps, size) = path.into_inner();
+    if let Some(response) = limits.reject(steps, size) {
+        return response;
+    }
+    let mut x = Diamond::new(0.5, steps * 2, random(), None);
+    match x
+        .generate(steps, None, |_| {})
+        .and_then(|()| image(&x, size, query.format))
+    {
+        Ok(f) => HttpResponse::Ok()
+            .content_type(query.format.content_type())
+            .body(f),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/hexagon/{a}/{b}/{c}/{size}")]
+async fn hexagon(
+    path: web::Path<(usize, usize, usize, usize)>,
+    query: web::Query<Query>,
+    limits: web::Data<Limits>,
+) -> HttpResponse {
+    let (a, b, c, size) = path.into_inner();
+    if let Some(response) = limits.reject_hexagon([a, b, c], size) {
+        return response;
+    }
+    let mut x = Hexagon::new(a, b, c, random());
+    x.sample();
+    match image(&x, size, query.format) {
+        Ok(f) => HttpResponse::Ok()
+            .content_type(query.format.content_type())
+            .body(f),
+        Err(err) => error_response(err),
+    }
+}
+
+/// Runs the server on `address:port` with `workers` worker threads, by
+/// default one per physical core, until it is stopped.
+pub fn serve(
+    address: &str,
+    port: u16,
+    workers: Option<usize>,
+    limits: Limits,
+) -> std::io::Result<()> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(limits))
+            .wrap(from_fn(limit_upload))
+            .service(index)
+            .service(hexagon)
+            .service(index_get)
+            .service(index_post)
+    });
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    actix_web::rt::System::new().block_on(server.bind((address, port))?.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_steps: 256,
+        max_side: 32,
+        max_weight_period: 64,
+        max_tile_size: 8,
+        max_upload: 1024,
+    };
+
+    #[test]
+    fn rejects_tiny_and_large_tiles() {
+        assert!(LIMITS.reject(16, 0).is_some());
+        assert!(LIMITS.reject(16, Limits::MIN_TILE_SIZE - 1).is_some());
+        assert!(LIMITS.reject(16, Limits::MIN_TILE_SIZE).is_none());
+        assert!(LIMITS.reject(16, 9).is_some());
+        assert!(LIMITS.reject(257, 4).is_some());
+    }
+
+    #[test]
+    fn rejects_empty_diamonds() {
+        assert!(LIMITS.reject(0, 4).is_some());
+        assert!(LIMITS.reject(1, 4).is_none());
+    }
+
+    #[test]
+    fn rejects_long_weight_periods() {
+        let block = "1:1:1:1";
+        let row = |cols: usize| vec![block; cols].join(",");
+        let small: Weights = vec![row(8); 8].join(";").parse().expect("weights");
+        let large: Weights = vec![row(8); 9].join(";").parse().expect("weights");
+        assert!(LIMITS.reject_weights(&small).is_none());
+        assert!(LIMITS.reject_weights(&large).is_some());
+    }
+
+    #[test]
+    fn rejects_long_hexagon_sides() {
+        assert!(LIMITS.reject_hexagon([32, 32, 32], 4).is_none());
+        assert!(LIMITS.reject_hexagon([1, 33, 1], 4).is_some());
+        assert!(LIMITS.reject_hexagon([4, 4, 4], 0).is_some());
+    }
+}
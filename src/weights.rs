@@ -0,0 +1,146 @@
+use crate::diamond::Coords;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Periodic edge weights for weighted domino shuffling.
+///
+/// Each entry holds the `[top, bottom, left, right]` weights of the four
+/// dominoes inside one odd 2x2 block, indexed by the block's top-left cell
+/// relative to the diamond center modulo `rows` x `cols`. `levels[k - 1]`
+/// holds the weights used when the diamond reaches order `k`, obtained from
+/// the requested pattern at the final order by repeated urban renewal, see
+/// [`Weights::with_order`]. A diamond cannot grow past the order its weights
+/// were computed for.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Weights {
+    rows: usize,
+    cols: usize,
+    levels: Vec<Vec<[f64; 4]>>,
+}
+
+impl Weights {
+    fn index(&self, i: isize, j: isize) -> usize {
+        i.rem_euclid(self.rows as isize) as usize * self.cols
+            + j.rem_euclid(self.cols as isize) as usize
+    }
+    /// Descriptions of everything that would make the weights unusable.
+    pub(crate) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.rows == 0 || self.cols == 0 {
+            problems.push(format!("weight pattern is {}x{}", self.rows, self.cols));
+        }
+        if self.levels.is_empty() {
+            problems.push("weights have no levels".to_string());
+        }
+        self.levels.iter().enumerate().for_each(|(k, level)| {
+            if level.len() != self.rows * self.cols {
+                problems.push(format!(
+                    "weight level {} has {} entries instead of {}",
+                    k + 1,
+                    level.len(),
+                    self.rows * self.cols
+                ));
+            }
+            if level.iter().flatten().any(|w| !(*w > 0.0 && w.is_finite())) {
+                problems.push(format!("weight level {} is not positive", k + 1));
+            }
+        });
+        problems
+    }
+    /// Computes the weights for sampling the order `order` diamond with the
+    /// requested pattern, which parsing alone only does for order 1.
+    pub fn with_order(self, order: usize) -> Weights {
+        let top = self.levels.last().cloned().unwrap_or_default();
+        let mut levels = vec![top];
+        (1..order).for_each(|_| {
+            let renewed = self.renew(&levels[levels.len() - 1]);
+            levels.push(renewed);
+        });
+        levels.reverse();
+        Weights { levels, ..self }
+    }
+    /// The order the weights were computed for.
+    pub fn order(&self) -> usize {
+        self.levels.len()
+    }
+    /// Number of blocks in one period of the pattern, `rows * cols`.
+    pub fn period(&self) -> usize {
+        self.rows * self.cols
+    }
+    fn renew(&self, w: &[[f64; 4]]) -> Vec<[f64; 4]> {
+        let shifts = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        let mut renewed: Vec<[f64; 4]> = (0..self.rows as isize)
+            .flat_map(|i| (0..self.cols as isize).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let mut out = [0.0; 4];
+                shifts.iter().enumerate().for_each(|(d, (di, dj))| {
+                    let n = w[self.index(i + di, j + dj)];
+                    out[d] = n[d] / (n[0] * n[1] + n[2] * n[3]);
+                });
+                out
+            })
+            .collect();
+        let max = renewed.iter().flatten().fold(0.0f64, |m, x| m.max(*x));
+        renewed.iter_mut().flatten().for_each(|x| *x /= max);
+        renewed
+    }
+    pub(crate) fn horizontal_probability(&self, order: usize, c: Coords) -> f64 {
+        let [t, b, l, r] = self.levels[order.min(self.levels.len()) - 1]
+            [self.index(c.0 as isize - order as isize, c.1 as isize - order as isize)];
+        t * b / (t * b + l * r)
+    }
+    /// Probability that reverse shuffling refills the hole with top-left
+    /// cell `c` at order `order` horizontally, with a south-going domino
+    /// over a north-going one, rather than with an east-going domino left of
+    /// a west-going one. The four dominoes are halves of the neighbouring odd
+    /// blocks.
+    pub(crate) fn hole_probability(&self, order: usize, c: Coords) -> f64 {
+        let level = &self.levels[order.min(self.levels.len()) - 1];
+        let (i, j) = (c.0 as isize - order as isize, c.1 as isize - order as isize);
+        let h = level[self.index(i - 1, j)][1] * level[self.index(i + 1, j)][0];
+        let v = level[self.index(i, j - 1)][3] * level[self.index(i, j + 1)][2];
+        h / (h + v)
+    }
+}
+
+/// Parses a periodic weight pattern such as `1,2;3,4`.
+///
+/// Rows are separated by `;` and entries by `,`. An entry is either a single
+/// weight shared by all four dominoes of a block or `t:b:l:r`. The two-periodic
+/// Aztec diamond with weights `a` and `b` is `a;b`.
+impl FromStr for Weights {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Weights, String> {
+        let rows: Vec<Vec<[f64; 4]>> = input
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|entry| {
+                        let values = entry
+                            .split(':')
+                            .map(|v| v.trim().parse::<f64>().map_err(|e| format!("{v}: {e}")))
+                            .collect::<Result<Vec<f64>, String>>()?;
+                        if values.iter().any(|v| !(*v > 0.0 && v.is_finite())) {
+                            return Err(format!("{entry}: weights must be positive"));
+                        }
+                        match values[..] {
+                            [w] => Ok([w; 4]),
+                            [t, b, l, r] => Ok([t, b, l, r]),
+                            _ => Err(format!("{entry}: expected 1 or 4 weights")),
+                        }
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, String>>()?;
+        let cols = rows[0].len();
+        if rows.iter().any(|row| row.len() != cols) {
+            return Err("all rows must have the same length".to_string());
+        }
+        Ok(Weights {
+            rows: rows.len(),
+            cols,
+            levels: vec![rows.concat()],
+        })
+    }
+}